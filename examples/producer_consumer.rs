@@ -0,0 +1,95 @@
+//! Round-trips messages between two processes over a FIFO.
+//!
+//! ```text
+//! cargo run --example producer_consumer -- <fifo-path> <count>
+//! ```
+//!
+//! The process spawns a copy of itself as the consumer, creates the queue at `<fifo-path>`, and
+//! sends `<count>` messages. The consumer prints each message it receives on its own line.
+
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+    time::{Duration, Instant},
+};
+
+use quipe::{PipeQueue, PipeReader};
+
+const CONSUME_FLAG: &str = "--consume";
+/// How long each side waits for the other to show up.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn usage() -> ! {
+    eprintln!("usage: producer_consumer <fifo-path> <count>");
+    std::process::exit(2);
+}
+
+fn produce(path: &Path, count: usize) -> Result<(), Box<dyn Error>> {
+    let mut consumer = Command::new(std::env::current_exe()?)
+        .arg(CONSUME_FLAG)
+        .arg(path)
+        .arg(count.to_string())
+        .spawn()?;
+
+    let sent = send(path, count);
+    if sent.is_err() {
+        // The consumer may be blocked opening a queue that no writer will ever open.
+        let _ = consumer.kill();
+        let _ = consumer.wait();
+        return sent;
+    }
+
+    let status = consumer.wait()?;
+    if !status.success() {
+        return Err(format!("consumer exited with {status}").into());
+    }
+    Ok(())
+}
+
+fn send(path: &Path, count: usize) -> Result<(), Box<dyn Error>> {
+    // Sends fail while the queue has no reader, so wait for the consumer to attach before sending.
+    let queue = PipeQueue::create_wait(path, STARTUP_TIMEOUT)?;
+    for i in 0..count {
+        queue.send(format!("message {i}").as_bytes())?;
+    }
+    Ok(())
+}
+
+fn consume(path: &Path, count: usize) -> Result<(), Box<dyn Error>> {
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while !path.exists() {
+        if Instant::now() >= deadline {
+            return Err(format!("no queue appeared at {}", path.display()).into());
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    let reader = PipeReader::new(path)?;
+    for _ in 0..count {
+        let message = reader.receive()?;
+        println!("{}", String::from_utf8_lossy(&message));
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    let consumer = args.first().is_some_and(|arg| arg == CONSUME_FLAG);
+    if consumer {
+        args.remove(0);
+    }
+    let [path, count] = args.as_slice() else {
+        usage();
+    };
+    let path = PathBuf::from(path);
+    let Ok(count) = count.parse::<usize>() else {
+        usage();
+    };
+
+    if consumer {
+        consume(&path, count)
+    } else {
+        produce(&path, count)
+    }
+}
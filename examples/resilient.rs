@@ -0,0 +1,114 @@
+//! Keeps a producer going while its consumer restarts.
+//!
+//! ```text
+//! cargo run --example resilient -- <fifo-path> <count> [messages-per-reader]
+//! ```
+//!
+//! The consumer closes its reader after every `[messages-per-reader]` messages and opens a new one
+//! a moment later, like a service being restarted. A send in between fails with
+//! `ErrorKind::NoReaders`; the producer then reopens the queue with `NoReader::Block`, which waits
+//! for the next reader, and sends the same message again. The consumer prints every message on
+//! its own line, and the producer reports each reconnect on stderr.
+//!
+//! Messages still in the pipe when the last reader closes it are lost, so the consumer confirms
+//! each one over a channel before the next is sent. Across processes that would be a reply queue.
+
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::Duration,
+};
+
+use quipe::{ErrorKind, NoReader, PipeQueue, PipeReader};
+
+const DEFAULT_MESSAGES_PER_READER: usize = 3;
+const RESTART_DELAY: Duration = Duration::from_millis(20);
+
+fn usage() -> ! {
+    eprintln!("usage: resilient <fifo-path> <count> [messages-per-reader]");
+    std::process::exit(2);
+}
+
+fn connect(path: &Path) -> Result<PipeQueue, quipe::Error> {
+    PipeQueue::options()
+        .reuse_existing(true)
+        .if_no_reader(NoReader::Block)
+        .open(path)
+}
+
+fn produce(path: &Path, count: usize, acks: Receiver<()>) -> Result<(), Box<dyn Error>> {
+    let mut queue = connect(path)?;
+    for i in 0..count {
+        let message = format!("message {i}");
+        loop {
+            match queue.send(message.as_bytes()) {
+                Ok(()) => break,
+                Err(error) if error.kind() == ErrorKind::NoReaders => {
+                    eprintln!("no readers, reconnecting");
+                    // Close the old write end first, so the only writer the next reader sees is
+                    // the one that is about to send.
+                    drop(queue);
+                    queue = connect(path)?;
+                }
+                Err(error) => return Err(error.into()),
+            }
+        }
+        acks.recv()?;
+    }
+    Ok(())
+}
+
+fn consume(
+    path: &Path,
+    count: usize,
+    per_reader: usize,
+    acks: Sender<()>,
+) -> Result<(), Box<dyn Error>> {
+    let mut received = 0;
+    while received < count {
+        let reader = PipeReader::options().create_if_missing(true).open(path)?;
+        let batch = per_reader.min(count - received);
+        for _ in 1..batch {
+            println!("{}", String::from_utf8_lossy(&reader.receive()?));
+            acks.send(())?;
+        }
+        println!("{}", String::from_utf8_lossy(&reader.receive()?));
+        // Close the reader before confirming the last message, so the next send finds it gone.
+        drop(reader);
+        acks.send(())?;
+        received += batch;
+        thread::sleep(RESTART_DELAY);
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let (path, count, per_reader) = match args.as_slice() {
+        [path, count] => (path, count, None),
+        [path, count, per_reader] => (path, count, Some(per_reader)),
+        _ => usage(),
+    };
+    let path = PathBuf::from(path);
+    let Ok(count) = count.parse::<usize>() else {
+        usage();
+    };
+    let per_reader = match per_reader.map(|per_reader| per_reader.parse::<usize>()) {
+        None => DEFAULT_MESSAGES_PER_READER,
+        Some(Ok(per_reader)) if per_reader > 0 => per_reader,
+        Some(_) => usage(),
+    };
+
+    let (ack_sender, acks) = mpsc::channel();
+    let consumer = thread::spawn({
+        let path = path.clone();
+        move || consume(&path, count, per_reader, ack_sender).map_err(|error| error.to_string())
+    });
+    // If the producer fails, the consumer may be waiting for a writer that never comes, so it is
+    // only joined after a clean run.
+    produce(&path, count, acks)?;
+    consumer.join().map_err(|_| "consumer panicked")??;
+    Ok(())
+}
@@ -0,0 +1,88 @@
+//! Sends typed values over a FIFO instead of raw bytes. Needs the `serde` feature.
+//!
+//! ```text
+//! cargo run --features serde --example typed_channel -- <fifo-path> <count>
+//! ```
+//!
+//! A producer thread sends `<count>` orders as JSON, followed by a `Close`. The consumer decodes
+//! each one back into an `Event` and prints it on its own line.
+
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    thread,
+};
+
+use quipe::{codec::JsonCodec, PipeQueue, PipeReader};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Event {
+    Order {
+        id: u32,
+        item: String,
+        quantity: u32,
+    },
+    Close,
+}
+
+fn usage() -> ! {
+    eprintln!("usage: typed_channel <fifo-path> <count>");
+    std::process::exit(2);
+}
+
+fn produce(queue: PipeQueue, count: u32) -> Result<(), quipe::Error> {
+    for id in 0..count {
+        let order = Event::Order {
+            id,
+            item: format!("item-{}", id % 3),
+            quantity: id + 1,
+        };
+        queue.send_serialized(&order)?;
+    }
+    queue.send_serialized(&Event::Close)
+}
+
+fn consume(reader: PipeReader) -> Result<(), quipe::Error> {
+    loop {
+        // `receive_deserialized` does the same with JSON; any other `Codec` plugs in here.
+        match reader.receive_decoded(&JsonCodec)? {
+            Event::Order { id, item, quantity } => println!("order {id}: {quantity} x {item}"),
+            Event::Close => return Ok(()),
+        }
+    }
+}
+
+fn run(path: &Path, count: u32) -> Result<(), Box<dyn Error>> {
+    // The reader is open before the first send, so the queue never finds itself without one.
+    let reader = PipeReader::options()
+        .create_if_missing(true)
+        .wait_for_writer(false)
+        .open(path)?;
+    let queue = PipeQueue::options()
+        .create_if_missing(false)
+        .reuse_existing(true)
+        .open(path)?;
+
+    thread::scope(|scope| {
+        let consumer = scope.spawn(|| consume(reader));
+        let produced = produce(queue, count);
+        let consumed = consumer.join().map_err(|_| "consumer panicked")?;
+        produced?;
+        consumed?;
+        Ok(())
+    })
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let [path, count] = args.as_slice() else {
+        usage();
+    };
+    let path = PathBuf::from(path);
+    let Ok(count) = count.parse::<u32>() else {
+        usage();
+    };
+
+    run(&path, count)
+}
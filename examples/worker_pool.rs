@@ -0,0 +1,103 @@
+//! Spreads jobs over a pool of worker threads, each with its own reader on one FIFO.
+//!
+//! ```text
+//! cargo run --example worker_pool -- <fifo-path> <count> [workers]
+//! ```
+//!
+//! The workers attach to the queue at `<fifo-path>` before anything is sent, then take turns
+//! receiving: each job goes to exactly one of them. Every worker prints the jobs it handled, one
+//! per line, and stops at the empty message the producer sends it once all jobs are queued.
+
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    thread,
+};
+
+use quipe::{PipeQueue, PipeReader};
+
+const DEFAULT_WORKERS: usize = 4;
+
+fn usage() -> ! {
+    eprintln!("usage: worker_pool <fifo-path> <count> [workers]");
+    std::process::exit(2);
+}
+
+fn work(id: usize, reader: PipeReader) -> Result<(), quipe::Error> {
+    loop {
+        let job = reader.receive()?;
+        if job.is_empty() {
+            return Ok(());
+        }
+        println!("worker {id}: {}", String::from_utf8_lossy(&job));
+    }
+}
+
+fn run(path: &Path, count: usize, workers: usize) -> Result<(), Box<dyn Error>> {
+    // Every reader is open before the first send, so no job can find the queue without one, and
+    // none of them waits for the writer that isn't there yet.
+    let readers = (0..workers)
+        .map(|_| {
+            PipeReader::options()
+                .create_if_missing(true)
+                .wait_for_writer(false)
+                .open(path)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let queue = PipeQueue::options()
+        .create_if_missing(false)
+        .reuse_existing(true)
+        .open(path)?;
+
+    thread::scope(|scope| {
+        let handles = readers
+            .into_iter()
+            .enumerate()
+            .map(|(id, reader)| scope.spawn(move || work(id, reader)))
+            .collect::<Vec<_>>();
+        // The queue is dropped once this returns, so if a send fails the workers see every
+        // writer gone instead of waiting for jobs forever.
+        let sent = send_jobs(queue, count, workers);
+        let results = handles
+            .into_iter()
+            .map(|handle| handle.join())
+            .collect::<Vec<_>>();
+        sent?;
+        for result in results {
+            result.map_err(|_| "worker panicked")??;
+        }
+        Ok(())
+    })
+}
+
+fn send_jobs(queue: PipeQueue, count: usize, workers: usize) -> Result<(), quipe::Error> {
+    for i in 0..count {
+        queue.send(format!("job {i}").as_bytes())?;
+    }
+    // One stop message per worker. A worker that got one has stopped receiving, so the others
+    // get the rest.
+    for _ in 0..workers {
+        queue.send(b"")?;
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let (path, count, workers) = match args.as_slice() {
+        [path, count] => (path, count, None),
+        [path, count, workers] => (path, count, Some(workers)),
+        _ => usage(),
+    };
+    let path = PathBuf::from(path);
+    let Ok(count) = count.parse::<usize>() else {
+        usage();
+    };
+    let workers = match workers.map(|workers| workers.parse::<usize>()) {
+        None => DEFAULT_WORKERS,
+        Some(Ok(workers)) if workers > 0 => workers,
+        Some(_) => usage(),
+    };
+
+    run(&path, count, workers)
+}
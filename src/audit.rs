@@ -0,0 +1,287 @@
+//! Permission checks for queues in security-sensitive deployments.
+//!
+//! `audit` inspects an existing FIFO and its parent directory and reports anything that would let
+//! another user read, inject or replace messages. The expectations are: a FIFO (not a symlink to
+//! one) owned by the current user that only its owner can access, such as the 0700 that
+//! `PipeQueue::create` uses, in a directory that other users cannot write. That includes sticky
+//! directories like /tmp: other users can't replace the FIFO there, but they can create the path
+//! before it exists. `SecurityPreset::Strict` refuses the same things when opening a queue.
+
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// Not exploitable by itself, but looser than the strict expectations.
+    Warning,
+    /// Lets another user read, inject or replace messages.
+    Critical,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditCheck {
+    /// The path is a symlink rather than the FIFO itself.
+    Symlink,
+    /// The path is not a FIFO.
+    FileType,
+    /// The FIFO's permission bits.
+    FileMode,
+    /// The FIFO's owner.
+    FileOwner,
+    /// The parent directory's permission bits.
+    ParentMode,
+    /// The parent directory's owner.
+    ParentOwner,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub check: AuditCheck,
+    pub severity: Severity,
+    pub observed: String,
+    pub expected: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct AuditReport {
+    pub path: PathBuf,
+    pub findings: Vec<Finding>,
+}
+
+impl AuditReport {
+    pub fn passed(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// Returns the finding for `check`, if that check failed.
+    pub fn finding(&self, check: AuditCheck) -> Option<&Finding> {
+        self.findings.iter().find(|finding| finding.check == check)
+    }
+}
+
+#[cfg(unix)]
+pub use self::unix::audit;
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        fs::Metadata,
+        os::unix::fs::{FileTypeExt, MetadataExt},
+        path::Path,
+    };
+
+    use super::*;
+    use crate::error::*;
+
+    /// Checks the queue at `path` and its parent directory against the strict expectations.
+    pub fn audit(path: &Path) -> Result<AuditReport> {
+        let uid = unsafe { libc::getuid() };
+        let mut findings = Vec::new();
+
+        let link_metadata = std::fs::symlink_metadata(path)?;
+        if link_metadata.file_type().is_symlink() {
+            findings.push(Finding {
+                check: AuditCheck::Symlink,
+                severity: Severity::Critical,
+                observed: "symlink".to_string(),
+                expected: "the FIFO itself".to_string(),
+            });
+        }
+        let metadata = std::fs::metadata(path)?;
+        check_file(&metadata, uid, &mut findings);
+
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        check_parent(&std::fs::metadata(parent)?, uid, &mut findings);
+
+        Ok(AuditReport {
+            path: path.to_path_buf(),
+            findings,
+        })
+    }
+
+    fn check_file(metadata: &Metadata, uid: libc::uid_t, findings: &mut Vec<Finding>) {
+        if !metadata.file_type().is_fifo() {
+            findings.push(Finding {
+                check: AuditCheck::FileType,
+                severity: Severity::Critical,
+                observed: "not a FIFO".to_string(),
+                expected: "FIFO".to_string(),
+            });
+        }
+
+        // Any owner-only mode passes, whatever the owner's own bits are.
+        let mode = metadata.mode() & 0o777;
+        if mode & 0o077 != 0 {
+            findings.push(Finding {
+                check: AuditCheck::FileMode,
+                severity: Severity::Critical,
+                observed: format!("{mode:04o}"),
+                expected: format!("{:04o}", mode & 0o700),
+            });
+        }
+
+        if metadata.uid() != uid {
+            findings.push(Finding {
+                check: AuditCheck::FileOwner,
+                severity: Severity::Critical,
+                observed: format!("uid {}", metadata.uid()),
+                expected: format!("uid {uid}"),
+            });
+        }
+    }
+
+    fn check_parent(metadata: &Metadata, uid: libc::uid_t, findings: &mut Vec<Finding>) {
+        let mode = metadata.mode() & 0o7777;
+        if mode & 0o022 != 0 {
+            // Even a sticky directory lets another user create the path first, e.g. as a FIFO
+            // they own and can read.
+            findings.push(Finding {
+                check: AuditCheck::ParentMode,
+                severity: Severity::Critical,
+                observed: format!("{mode:04o}"),
+                expected: "not group- or world-writable".to_string(),
+            });
+        }
+
+        if metadata.uid() != uid && metadata.uid() != 0 {
+            findings.push(Finding {
+                check: AuditCheck::ParentOwner,
+                severity: Severity::Warning,
+                observed: format!("uid {}", metadata.uid()),
+                expected: format!("uid {uid} or root"),
+            });
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::os::unix::fs::PermissionsExt;
+
+        use tempfile::tempdir;
+
+        use super::*;
+        use crate::{PipeQueue, PipeReader, SecurityPreset};
+
+        #[test]
+        fn test_strict_fifo_passes() {
+            let temp_dir = tempdir().unwrap();
+            let path = temp_dir.path().join("my_queue");
+            PipeQueue::create(&path).unwrap();
+            let report = audit(&path).unwrap();
+            assert!(report.passed(), "{:?}", report.findings);
+
+            let path = temp_dir.path().join("other_queue");
+            PipeQueue::options().mode(0o600).open(&path).unwrap();
+            let report = audit(&path).unwrap();
+            assert!(report.passed(), "{:?}", report.findings);
+        }
+
+        #[test]
+        fn test_loose_file_mode_is_reported() {
+            let temp_dir = tempdir().unwrap();
+            let path = temp_dir.path().join("my_queue");
+            PipeQueue::options().mode(0o666).open(&path).unwrap();
+
+            let report = audit(&path).unwrap();
+            let finding = report.finding(AuditCheck::FileMode).unwrap();
+            assert_eq!(finding.severity, Severity::Critical);
+            assert_eq!(finding.observed, "0666");
+            assert_eq!(finding.expected, "0600");
+            assert_eq!(report.findings.len(), 1);
+        }
+
+        #[test]
+        fn test_writable_parent_is_reported() {
+            let temp_dir = tempdir().unwrap();
+            let path = temp_dir.path().join("my_queue");
+            PipeQueue::create(&path).unwrap();
+            std::fs::set_permissions(temp_dir.path(), std::fs::Permissions::from_mode(0o777))
+                .unwrap();
+
+            let report = audit(&path).unwrap();
+            let finding = report.finding(AuditCheck::ParentMode).unwrap();
+            assert_eq!(finding.severity, Severity::Critical);
+            assert_eq!(finding.observed, "0777");
+
+            std::fs::set_permissions(temp_dir.path(), std::fs::Permissions::from_mode(0o1777))
+                .unwrap();
+            let report = audit(&path).unwrap();
+            let finding = report.finding(AuditCheck::ParentMode).unwrap();
+            assert_eq!(finding.severity, Severity::Critical);
+            assert_eq!(finding.observed, "1777");
+        }
+
+        #[test]
+        fn test_strict_preset_passes() {
+            let temp_dir = tempdir().unwrap();
+            let path = temp_dir.path().join("my_queue");
+            let _queue = PipeQueue::options()
+                .preset(SecurityPreset::Strict)
+                .open(&path)
+                .unwrap();
+            let report = audit(&path).unwrap();
+            assert!(report.passed(), "{:?}", report.findings);
+            assert_eq!(std::fs::metadata(&path).unwrap().mode() & 0o777, 0o600);
+
+            let path = temp_dir.path().join("other_queue");
+            let _reader = PipeReader::options()
+                .preset(SecurityPreset::Strict)
+                .create_if_missing(true)
+                .wait_for_writer(false)
+                .open(&path)
+                .unwrap();
+            let report = audit(&path).unwrap();
+            assert!(report.passed(), "{:?}", report.findings);
+        }
+
+        #[test]
+        fn test_strict_preset_refuses_what_audit_reports() {
+            let temp_dir = tempdir().unwrap();
+            let path = temp_dir.path().join("my_queue");
+
+            // Another user's FIFO can't be faked without root, but a loose mode stands in for it.
+            PipeQueue::options().mode(0o622).open(&path).unwrap();
+            let error = PipeQueue::options()
+                .preset(SecurityPreset::Strict)
+                .reuse_existing(true)
+                .open(&path)
+                .err()
+                .unwrap();
+            assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+
+            let link = temp_dir.path().join("link");
+            std::os::unix::fs::symlink(&path, &link).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+            let error = PipeQueue::options()
+                .preset(SecurityPreset::Strict)
+                .reuse_existing(true)
+                .open(&link)
+                .err()
+                .unwrap();
+            assert_eq!(error.kind(), ErrorKind::Os(libc::ELOOP));
+
+            std::fs::set_permissions(temp_dir.path(), std::fs::Permissions::from_mode(0o1777))
+                .unwrap();
+            let error = PipeQueue::options()
+                .preset(SecurityPreset::Strict)
+                .open(&temp_dir.path().join("new_queue"))
+                .err()
+                .unwrap();
+            assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+            assert!(!temp_dir.path().join("new_queue").exists());
+        }
+
+        #[test]
+        fn test_regular_file_is_reported() {
+            let temp_dir = tempdir().unwrap();
+            let path = temp_dir.path().join("my_queue");
+            std::fs::write(&path, b"").unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+            let report = audit(&path).unwrap();
+            assert!(report.finding(AuditCheck::FileType).is_some());
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Typed messages on top of the byte framing. Each message is one ordinary frame whose payload is
+//! the encoded value, so typed and untyped endpoints interoperate.
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{error::*, PipeQueue, PipeReader};
+
+/// Turns values into frame payloads and back.
+pub trait Codec {
+    fn encode<T: Serialize>(&self, message: &T) -> Result<Vec<u8>>;
+    fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> Result<T>;
+}
+
+/// Encodes messages as JSON.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn encode<T: Serialize>(&self, message: &T) -> Result<Vec<u8>> {
+        serde_json::to_vec(message).map_err(|error| {
+            Error::with_kind(
+                format!("failed to serialize message: {error}"),
+                ErrorKind::Codec,
+            )
+        })
+    }
+
+    fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> Result<T> {
+        serde_json::from_slice(payload).map_err(|error| {
+            Error::with_kind(
+                format!(
+                    "failed to deserialize {}-byte frame: {error}",
+                    payload.len()
+                ),
+                ErrorKind::Codec,
+            )
+        })
+    }
+}
+
+impl PipeQueue {
+    /// Sends `message` encoded as JSON.
+    pub fn send_serialized<T: Serialize>(&self, message: &T) -> Result<()> {
+        self.send_encoded(&JsonCodec, message)
+    }
+
+    pub fn send_encoded<C: Codec, T: Serialize>(&self, codec: &C, message: &T) -> Result<()> {
+        self.send(&codec.encode(message)?)
+    }
+}
+
+impl PipeReader {
+    /// Receives a message sent with `PipeQueue::send_serialized`.
+    pub fn receive_deserialized<T: DeserializeOwned>(&self) -> Result<T> {
+        self.receive_decoded(&JsonCodec)
+    }
+
+    pub fn receive_decoded<C: Codec, T: DeserializeOwned>(&self, codec: &C) -> Result<T> {
+        codec.decode(&self.receive()?)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{collections::HashMap, thread};
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::loopback;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Command {
+        Ping { id: u32 },
+        Upload { name: String, data: Vec<u8> },
+    }
+
+    #[test]
+    fn test_serialized_round_trip() {
+        let (queue, reader) = loopback(None).unwrap();
+        let commands = vec![
+            Command::Ping { id: 7 },
+            Command::Upload {
+                name: "blob".to_string(),
+                // Larger than PIPE_BUF, so the frame can't be written in one atomic chunk.
+                data: (0..8192).map(|i| i as u8).collect(),
+            },
+        ];
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for command in &commands {
+                    queue.send_serialized(command).unwrap();
+                }
+            });
+            for command in &commands {
+                assert_eq!(&reader.receive_deserialized::<Command>().unwrap(), command);
+            }
+        });
+    }
+
+    #[test]
+    fn test_deserialize_error_reports_frame_length() {
+        let (queue, reader) = loopback(None).unwrap();
+        queue.send(b"not json").unwrap();
+        let error = reader.receive_deserialized::<Command>().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Codec);
+        assert!(error.to_string().contains("8-byte frame"), "{error}");
+    }
+
+    #[test]
+    fn test_serialize_error_has_codec_kind() {
+        // JSON object keys must be strings.
+        let message = HashMap::from([(vec![1u8], 1)]);
+        let error = JsonCodec.encode(&message).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Codec);
+    }
+}
@@ -0,0 +1,223 @@
+//! Machine-checkable definition of the wire format, for implementations in other languages.
+//!
+//! `vectors` lists the canonical cases for each framing mode. `write_vectors` renders them into a
+//! directory as a `manifest.tsv` (name, mode and description per line) plus a `<name>.payload.hex`
+//! and `<name>.frame.hex` file per case. `check_frame` verifies that a payload and a frame agree
+//! with this crate's encoder and decoder.
+//!
+//! The rendered vectors are checked in under `tests/vectors`, and the crate's tests fail if the
+//! framing code drifts from them. Regenerate them intentionally with
+//! `QUIPE_REGENERATE_VECTORS=1 cargo test --test conformance`.
+
+use std::{fmt::Write as _, path::Path};
+
+use crate::{error::*, frame};
+
+const MANIFEST: &str = "manifest.tsv";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FramingMode {
+    /// A big-endian `u32` payload length followed by the payload.
+    Plain,
+    /// The marker `ff 51 50 46` ("\xffQPF"), a flags byte (`01`: CRC-32), the big-endian `u32`
+    /// payload length, the big-endian CRC-32 (IEEE, as in zlib) of the payload, and the payload.
+    Checked,
+}
+
+impl FramingMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            FramingMode::Plain => "plain",
+            FramingMode::Checked => "checked",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "plain" => Some(FramingMode::Plain),
+            "checked" => Some(FramingMode::Checked),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Vector {
+    pub name: String,
+    pub description: String,
+    pub mode: FramingMode,
+    pub payload: Vec<u8>,
+    pub frame: Vec<u8>,
+}
+
+fn encode(mode: FramingMode, payload: &[u8]) -> Result<Vec<u8>> {
+    match mode {
+        FramingMode::Plain => frame::encode(payload),
+        FramingMode::Checked => frame::encode_checked(payload),
+    }
+}
+
+fn decode(mode: FramingMode, frame_bytes: &[u8]) -> Result<&[u8]> {
+    match mode {
+        FramingMode::Plain => frame::decode(frame_bytes),
+        FramingMode::Checked => frame::decode_checked(frame_bytes),
+    }
+}
+
+/// Returns the canonical test vectors, with frames produced by this crate's encoder. Every case is
+/// framed in each mode; checked vectors carry a `_checked` suffix.
+pub fn vectors() -> Result<Vec<Vector>> {
+    let cases: [(&str, &str, Vec<u8>); 8] = [
+        ("empty", "zero-length payload", Vec::new()),
+        ("one_byte", "single-byte payload", vec![0x2a]),
+        ("hello", "short ASCII payload", b"Hello, reader!".to_vec()),
+        (
+            "header_lookalike",
+            "payload that looks like an empty frame's header",
+            vec![0; frame::HEADER_LEN],
+        ),
+        (
+            "all_byte_values",
+            "every byte value in order, so the length needs two bytes",
+            (0..=u8::MAX).collect(),
+        ),
+        (
+            "length_byte_order",
+            "length 0x0102 to pin down the header's byte order",
+            vec![0xa5; 0x0102],
+        ),
+        (
+            "pipe_buf_plus_one",
+            "payload one byte larger than Linux's 4096-byte PIPE_BUF",
+            (0..4097).map(|i| (i % 251) as u8).collect(),
+        ),
+        (
+            "crc_check_input",
+            "the standard CRC-32 check input, whose checksum is cbf43926",
+            b"123456789".to_vec(),
+        ),
+    ];
+    let mut vectors = Vec::with_capacity(2 * cases.len());
+    for (mode, suffix) in [(FramingMode::Plain, ""), (FramingMode::Checked, "_checked")] {
+        for (name, description, payload) in &cases {
+            vectors.push(Vector {
+                name: format!("{name}{suffix}"),
+                description: description.to_string(),
+                mode,
+                frame: encode(mode, payload)?,
+                payload: payload.clone(),
+            });
+        }
+    }
+    Ok(vectors)
+}
+
+/// Verifies that `frame_bytes` is exactly how `mode` frames `payload`, and that decoding it yields
+/// `payload` again.
+pub fn check_frame(mode: FramingMode, payload: &[u8], frame_bytes: &[u8]) -> Result<()> {
+    let encoded = encode(mode, payload)?;
+    if encoded != frame_bytes {
+        return Err(Error::new(format!(
+            "{} encoding of a {}-byte payload does not match the expected frame",
+            mode.name(),
+            payload.len()
+        )));
+    }
+    let decoded = decode(mode, frame_bytes)?;
+    if decoded != payload {
+        return Err(Error::new(format!(
+            "{} decoding of a {}-byte frame does not yield the expected payload",
+            mode.name(),
+            frame_bytes.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Renders `vectors()` into `dir`, creating it if needed.
+pub fn write_vectors(dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let mut manifest = String::new();
+    for vector in vectors()? {
+        // Writing to a String can't fail.
+        let _ = writeln!(
+            manifest,
+            "{}\t{}\t{}",
+            vector.name,
+            vector.mode.name(),
+            vector.description
+        );
+        std::fs::write(
+            dir.join(format!("{}.payload.hex", vector.name)),
+            to_hex(&vector.payload),
+        )?;
+        std::fs::write(
+            dir.join(format!("{}.frame.hex", vector.name)),
+            to_hex(&vector.frame),
+        )?;
+    }
+    std::fs::write(dir.join(MANIFEST), manifest)?;
+    Ok(())
+}
+
+/// Loads vectors previously rendered by `write_vectors`.
+pub fn read_vectors(dir: &Path) -> Result<Vec<Vector>> {
+    let manifest = std::fs::read_to_string(dir.join(MANIFEST))?;
+    let mut vectors = Vec::new();
+    for line in manifest.lines() {
+        let mut fields = line.splitn(3, '\t');
+        let (Some(name), Some(mode), Some(description)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(Error::new(format!("malformed manifest line {line:?}")));
+        };
+        let mode = FramingMode::from_name(mode)
+            .ok_or_else(|| Error::new(format!("unknown framing mode {mode:?}")))?;
+        let read_hex = |suffix: &str| -> Result<Vec<u8>> {
+            let path = dir.join(format!("{name}.{suffix}.hex"));
+            from_hex(std::fs::read_to_string(&path)?.trim())
+                .ok_or_else(|| Error::new(format!("invalid hex in {}", path.display())))
+        };
+        vectors.push(Vector {
+            name: name.to_string(),
+            description: description.to_string(),
+            mode,
+            payload: read_hex("payload")?,
+            frame: read_hex("frame")?,
+        });
+    }
+    Ok(vectors)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2 + 1);
+    for byte in bytes {
+        // Writing to a String can't fail.
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex.push('\n');
+    hex
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex_round_trip() {
+        let bytes = (0..=u8::MAX).collect::<Vec<_>>();
+        assert_eq!(from_hex(to_hex(&bytes).trim()).unwrap(), bytes);
+        assert_eq!(from_hex("0"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+}
@@ -2,24 +2,6 @@ use std::ffi::CStr;
 
 use libc::strerror;
 
-#[allow(dead_code)]
-#[cfg(target_os = "linux")]
-pub mod errors {
-    use libc::__errno_location;
-    pub fn get_errno() -> c_int {
-        unsafe { *__errno_location() }
-    }
-}
-
-#[allow(dead_code)]
-#[cfg(target_os = "macos")]
-pub mod errors {
-    use libc::__error;
-    pub fn get_errno() -> libc::c_int {
-        unsafe { *__error() }
-    }
-}
-
 #[derive(Copy, Clone)]
 pub struct Errno {
     errno: libc::c_int,
@@ -28,15 +10,24 @@ pub struct Errno {
 impl Errno {
     pub fn latest() -> Self {
         Self {
-            errno: errors::get_errno(),
+            errno: std::io::Error::last_os_error().raw_os_error().unwrap_or(0),
         }
     }
+    pub fn raw(self) -> libc::c_int {
+        self.errno
+    }
     pub fn is_enoent(self) -> bool {
         self.errno == libc::ENOENT
     }
     pub fn is_eagain(self) -> bool {
         self.errno == libc::EAGAIN
     }
+    pub fn is_eintr(self) -> bool {
+        self.errno == libc::EINTR
+    }
+    pub fn is_epipe(self) -> bool {
+        self.errno == libc::EPIPE
+    }
     pub fn is_error(self) -> bool {
         self.errno != 0
     }
@@ -2,10 +2,96 @@ use std::{num::ParseIntError, panic::Location};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// What went wrong, for callers that need to react to particular failures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The FIFO already exists (EEXIST).
+    AlreadyExists,
+    /// The path does not exist (ENOENT).
+    NotFound,
+    /// The path or an operation on it is not permitted (EACCES, EPERM).
+    PermissionDenied,
+    /// The operation would have blocked (EAGAIN).
+    WouldBlock,
+    /// A send found no reader attached to the queue (EPIPE). The message was not delivered.
+    NoReaders,
+    /// Every writer closed the queue before a message (or the rest of one) arrived.
+    Disconnected,
+    /// A syscall was interrupted by a signal (EINTR).
+    Interrupted,
+    /// A wait ran out of time.
+    TimedOut,
+    /// Bytes that don't form a valid frame.
+    InvalidFrame,
+    /// A message to send, or a frame's declared length, is over the size limit.
+    MessageTooLarge,
+    /// An argument can't be passed to the OS, e.g. a path with an interior NUL byte.
+    InvalidInput,
+    /// A `Codec` couldn't encode a message, or decode a payload.
+    Codec,
+    /// The target has no FIFOs.
+    Unsupported,
+    /// A syscall failed with an errno that has no more specific kind.
+    Os(i32),
+    /// Any other failure.
+    Other,
+}
+
+impl ErrorKind {
+    /// Maps the `std::io::ErrorKind`s that have a counterpart; everything else is `Other`.
+    fn from_io_kind(kind: std::io::ErrorKind) -> Self {
+        match kind {
+            std::io::ErrorKind::AlreadyExists => ErrorKind::AlreadyExists,
+            std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+            std::io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+            std::io::ErrorKind::WouldBlock => ErrorKind::WouldBlock,
+            std::io::ErrorKind::BrokenPipe => ErrorKind::NoReaders,
+            std::io::ErrorKind::UnexpectedEof => ErrorKind::Disconnected,
+            std::io::ErrorKind::Interrupted => ErrorKind::Interrupted,
+            std::io::ErrorKind::TimedOut => ErrorKind::TimedOut,
+            std::io::ErrorKind::Unsupported => ErrorKind::Unsupported,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    pub(crate) fn from_errno(errno: i32) -> Self {
+        match Self::from_io_kind(std::io::Error::from_raw_os_error(errno).kind()) {
+            ErrorKind::Other => ErrorKind::Os(errno),
+            kind => kind,
+        }
+    }
+
+    fn io_kind(self) -> std::io::ErrorKind {
+        match self {
+            ErrorKind::AlreadyExists => std::io::ErrorKind::AlreadyExists,
+            ErrorKind::NotFound => std::io::ErrorKind::NotFound,
+            ErrorKind::PermissionDenied => std::io::ErrorKind::PermissionDenied,
+            ErrorKind::WouldBlock => std::io::ErrorKind::WouldBlock,
+            ErrorKind::NoReaders => std::io::ErrorKind::BrokenPipe,
+            ErrorKind::Disconnected => std::io::ErrorKind::UnexpectedEof,
+            ErrorKind::Interrupted => std::io::ErrorKind::Interrupted,
+            ErrorKind::TimedOut => std::io::ErrorKind::TimedOut,
+            ErrorKind::InvalidFrame => std::io::ErrorKind::InvalidData,
+            ErrorKind::MessageTooLarge => std::io::ErrorKind::InvalidInput,
+            ErrorKind::InvalidInput => std::io::ErrorKind::InvalidInput,
+            ErrorKind::Codec => std::io::ErrorKind::InvalidData,
+            ErrorKind::Unsupported => std::io::ErrorKind::Unsupported,
+            ErrorKind::Os(errno) => std::io::Error::from_raw_os_error(errno).kind(),
+            ErrorKind::Other => std::io::ErrorKind::Other,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Error {
+    kind: ErrorKind,
     message: String,
     location: &'static Location<'static>,
+    /// The errno behind the failure, when it came from a syscall.
+    os_error: Option<i32>,
+    /// The kind of a non-OS `std::io::Error` this was converted from.
+    io_kind: Option<std::io::ErrorKind>,
 }
 
 impl Error {
@@ -13,8 +99,79 @@ impl Error {
     #[track_caller]
     pub fn new(message: impl Into<String>) -> Self {
         Self {
+            kind: ErrorKind::Other,
             message: message.into(),
             location: Location::caller(),
+            os_error: None,
+            io_kind: None,
+        }
+    }
+
+    /// Creates an error for a failed syscall that set `errno`.
+    #[allow(dead_code)]
+    #[track_caller]
+    pub(crate) fn with_os_error(message: impl Into<String>, errno: i32) -> Self {
+        Self {
+            kind: ErrorKind::from_errno(errno),
+            os_error: Some(errno),
+            ..Self::new(message)
+        }
+    }
+
+    /// Creates an error of `kind` without an errno behind it (e.g. a timeout).
+    #[allow(dead_code)]
+    #[track_caller]
+    pub(crate) fn with_kind(message: impl Into<String>, kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            ..Self::new(message)
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The errno behind this error, if it came from a syscall.
+    pub fn raw_os_error(&self) -> Option<i32> {
+        self.os_error
+    }
+
+    /// The closest `std::io::ErrorKind`: derived from the errno when there is one, then from the
+    /// `std::io::Error` this was converted from, then from `kind`.
+    pub fn io_error_kind(&self) -> std::io::ErrorKind {
+        match (self.os_error, self.io_kind) {
+            (Some(errno), _) => std::io::Error::from_raw_os_error(errno).kind(),
+            (None, Some(kind)) => kind,
+            (None, None) => self.kind.io_kind(),
+        }
+    }
+
+    /// Converts an `std::io::Error` back into an `Error`. An `Error` that was wrapped by
+    /// `From<Error> for std::io::Error` comes back unchanged; any other error keeps its errno and
+    /// kind.
+    #[track_caller]
+    pub fn from_io_preserving_kind(error: std::io::Error) -> Self {
+        if error.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            let kind = error.kind();
+            return match error.into_inner().map(|inner| inner.downcast::<Error>()) {
+                Some(Ok(error)) => *error,
+                // The inner error was checked above, so this is only here to avoid a panic.
+                _ => Self {
+                    kind: ErrorKind::from_io_kind(kind),
+                    io_kind: Some(kind),
+                    ..Self::new("io error")
+                },
+            };
+        }
+        Self {
+            kind: error
+                .raw_os_error()
+                .map_or(ErrorKind::from_io_kind(error.kind()), ErrorKind::from_errno),
+            message: format!("io error: {error:?}"),
+            location: Location::caller(),
+            os_error: error.raw_os_error(),
+            io_kind: Some(error.kind()),
         }
     }
 }
@@ -27,12 +184,53 @@ impl std::fmt::Display for Error {
 
 impl std::error::Error for Error {}
 
+/// A `PipeQueue::send_batch` that failed partway through.
+#[derive(Debug)]
+pub struct BatchError {
+    /// How many messages from the start of the batch were written in full before the failure.
+    /// Resending from this index neither drops nor duplicates any.
+    pub sent: usize,
+    pub error: Error,
+}
+
+impl BatchError {
+    pub fn kind(&self) -> ErrorKind {
+        self.error.kind()
+    }
+}
+
+impl std::fmt::Display for BatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "batch failed after {} messages: {}",
+            self.sent, self.error
+        )
+    }
+}
+
+impl std::error::Error for BatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Drops the count, for callers that only need to know that the batch failed.
+impl From<BatchError> for Error {
+    fn from(error: BatchError) -> Self {
+        error.error
+    }
+}
+
 impl From<Box<dyn std::error::Error>> for Error {
     #[track_caller]
     fn from(error: Box<dyn std::error::Error>) -> Self {
         Self {
+            kind: ErrorKind::Other,
             message: format!("dyn error: {error:?}"),
             location: Location::caller(),
+            os_error: None,
+            io_kind: None,
         }
     }
 }
@@ -40,9 +238,20 @@ impl From<Box<dyn std::error::Error>> for Error {
 impl From<std::io::Error> for Error {
     #[track_caller]
     fn from(error: std::io::Error) -> Self {
-        Self {
-            message: format!("io error: {error:?}"),
-            location: Location::caller(),
+        Self::from_io_preserving_kind(error)
+    }
+}
+
+/// An `Error` from a syscall becomes the io error for its errno, so `raw_os_error` and code that
+/// matches on it keep working. An io error can't hold an errno and a payload at once, so the
+/// message and location are dropped then; `Error::from_io_preserving_kind` still recovers the
+/// errno and kind. Any other `Error` is wrapped whole as the io error's inner error, with the kind
+/// from `Error::io_error_kind`, and comes back unchanged.
+impl From<Error> for std::io::Error {
+    fn from(error: Error) -> Self {
+        match error.os_error {
+            Some(errno) => std::io::Error::from_raw_os_error(errno),
+            None => std::io::Error::new(error.io_error_kind(), error),
         }
     }
 }
@@ -51,8 +260,11 @@ impl From<String> for Error {
     #[track_caller]
     fn from(error: String) -> Self {
         Self {
+            kind: ErrorKind::Other,
             message: format!("error: {error}"),
             location: Location::caller(),
+            os_error: None,
+            io_kind: None,
         }
     }
 }
@@ -61,8 +273,11 @@ impl From<&str> for Error {
     #[track_caller]
     fn from(error: &str) -> Self {
         Self {
+            kind: ErrorKind::Other,
             message: format!("error: {error}"),
             location: Location::caller(),
+            os_error: None,
+            io_kind: None,
         }
     }
 }
@@ -71,8 +286,80 @@ impl From<ParseIntError> for Error {
     #[track_caller]
     fn from(error: ParseIntError) -> Self {
         Self {
+            kind: ErrorKind::Other,
             message: format!("parse int error: {error:?}"),
             location: Location::caller(),
+            os_error: None,
+            io_kind: None,
         }
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    #[test]
+    fn test_broken_pipe_round_trip() {
+        let io_error = io::Error::from(Error::with_os_error("failed to write", libc::EPIPE));
+        assert_eq!(io_error.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(io_error.raw_os_error(), Some(libc::EPIPE));
+
+        let error = Error::from_io_preserving_kind(io_error);
+        assert_eq!(error.raw_os_error(), Some(libc::EPIPE));
+        assert_eq!(error.io_error_kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(error.kind(), ErrorKind::NoReaders);
+    }
+
+    #[test]
+    fn test_non_os_error_round_trip() {
+        let io_error = io::Error::from(Error::with_kind("bad frame", ErrorKind::InvalidFrame));
+        assert_eq!(io_error.raw_os_error(), None);
+        let error = Error::from_io_preserving_kind(io_error);
+        assert_eq!(error.kind(), ErrorKind::InvalidFrame);
+        assert!(error.to_string().starts_with("bad frame [location="));
+    }
+
+    #[test]
+    fn test_timeout_round_trip() {
+        let error = Error::from_io_preserving_kind(io::Error::from(io::ErrorKind::TimedOut));
+        assert_eq!(error.raw_os_error(), None);
+        assert_eq!(error.io_error_kind(), io::ErrorKind::TimedOut);
+        assert_eq!(io::Error::from(error).kind(), io::ErrorKind::TimedOut);
+
+        let error = Error::with_os_error("timed out", libc::ETIMEDOUT);
+        assert_eq!(io::Error::from(error).kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_os_io_error_keeps_errno() {
+        let error: Error = io::Error::from_raw_os_error(libc::ENOENT).into();
+        assert_eq!(error.raw_os_error(), Some(libc::ENOENT));
+        assert_eq!(error.io_error_kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_errno_kinds() {
+        let error = Error::with_os_error("failed to open", libc::EEXIST);
+        assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+        // Errnos without a more specific kind keep their number.
+        let error = Error::with_os_error("failed to open", libc::ENXIO);
+        assert_eq!(error.kind(), ErrorKind::Os(libc::ENXIO));
+        let error: Error = io::Error::from_raw_os_error(libc::EINTR).into();
+        assert_eq!(error.kind(), ErrorKind::Interrupted);
+
+        let error = Error::with_kind("bad frame", ErrorKind::InvalidFrame);
+        assert_eq!(error.raw_os_error(), None);
+        assert_eq!(io::Error::from(error).kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_plain_error_maps_to_other() {
+        assert_eq!(
+            io::Error::from(Error::new("oops")).kind(),
+            io::ErrorKind::Other
+        );
+    }
+}
@@ -0,0 +1,287 @@
+//! The wire format. A plain frame is a big-endian `u32` payload length followed by the payload.
+//!
+//! A checked frame starts with the 4-byte `MAGIC` marker, then a flags byte (currently always
+//! `FLAG_CRC32`), the big-endian `u32` payload length, the big-endian CRC-32 (IEEE) of the payload
+//! and the payload itself. The marker begins with 0xff, which no plain length does, so readers
+//! tell the two apart from the first byte, and a reader that only knows plain frames sees a length
+//! of over 4 GiB instead of a plausible message.
+
+use std::time::Instant;
+
+use crate::error::*;
+
+/// Size of the length header that precedes every payload.
+pub(crate) const HEADER_LEN: usize = std::mem::size_of::<u32>();
+
+/// Marks the start of a checked frame.
+pub(crate) const MAGIC: [u8; 4] = [0xff, b'Q', b'P', b'F'];
+
+/// The only flag so far: the header carries a CRC-32 of the payload.
+pub(crate) const FLAG_CRC32: u8 = 0x01;
+
+/// Size of a checked frame's marker, flags, length and checksum.
+pub(crate) const CHECKED_HEADER_LEN: usize = MAGIC.len() + 1 + 2 * std::mem::size_of::<u32>();
+
+/// Plain lengths stay below this, so their first byte can never be the marker's.
+const MAX_PLAIN_LEN: u32 = 0xff00_0000;
+
+/// Timing and size information for a received frame.
+///
+/// A pipe doesn't record when bytes were written to it, so `arrived` is when the reader found the
+/// frame's first bytes waiting. A receive called after the frame was sent reports the time of the
+/// call, not of the send. How long the frame then took to reach its handler is
+/// `arrived.elapsed()`, read wherever the handler wants to measure it.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameMeta {
+    /// When the frame started being readable, taken once per frame. `None` if the reader was
+    /// opened with `ReaderOptions::timestamps(false)`.
+    pub arrived: Option<Instant>,
+    /// Payload length in bytes.
+    pub len: usize,
+}
+
+pub(crate) fn frame_header(len: usize) -> Result<[u8; HEADER_LEN]> {
+    match u32::try_from(len) {
+        Ok(len) if len < MAX_PLAIN_LEN => Ok(len.to_be_bytes()),
+        _ => Err(Error::with_kind(
+            format!("message too long ({len} bytes)"),
+            ErrorKind::MessageTooLarge,
+        )),
+    }
+}
+
+/// Fails with `ErrorKind::MessageTooLarge` if a `len`-byte payload is over `max_len`.
+#[cfg_attr(not(unix), allow(dead_code))]
+pub(crate) fn check_len(len: usize, max_len: usize) -> Result<()> {
+    if len > max_len {
+        Err(Error::with_kind(
+            format!("{len}-byte message is over the limit of {max_len} bytes"),
+            ErrorKind::MessageTooLarge,
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+pub(crate) fn payload_len(header: [u8; HEADER_LEN]) -> u32 {
+    u32::from_be_bytes(header)
+}
+
+/// Frames `payload` for the wire.
+pub(crate) fn encode(payload: &[u8]) -> Result<Vec<u8>> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&frame_header(payload.len())?);
+    frame.extend_from_slice(payload);
+    Ok(frame)
+}
+
+/// Returns the payload of a single complete plain frame.
+pub(crate) fn decode(frame: &[u8]) -> Result<&[u8]> {
+    let Some((header, payload)) = frame.split_first_chunk::<HEADER_LEN>() else {
+        return Err(Error::with_kind(
+            format!("frame of {} bytes is shorter than its header", frame.len()),
+            ErrorKind::InvalidFrame,
+        ));
+    };
+    if is_checked(*header) {
+        return Err(Error::with_kind(
+            "checked frame where a plain one was expected",
+            ErrorKind::InvalidFrame,
+        ));
+    }
+    let len = payload_len(*header) as usize;
+    if len != payload.len() {
+        return Err(Error::with_kind(
+            format!(
+                "frame declares {len} payload bytes but carries {}",
+                payload.len()
+            ),
+            ErrorKind::InvalidFrame,
+        ));
+    }
+    Ok(payload)
+}
+
+/// A validated checked-frame header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct CheckedHeader {
+    pub len: u32,
+    pub crc32: u32,
+}
+
+/// Whether a frame starting with `header` (the first `HEADER_LEN` bytes) is a checked frame.
+pub(crate) fn is_checked(header: [u8; HEADER_LEN]) -> bool {
+    header[0] == MAGIC[0]
+}
+
+/// Fails with `ErrorKind::InvalidFrame` unless `magic` is the checked-frame marker.
+pub(crate) fn check_magic(magic: [u8; MAGIC.len()]) -> Result<()> {
+    if magic == MAGIC {
+        Ok(())
+    } else {
+        Err(Error::with_kind(
+            format!("frame starts with {magic:02x?} instead of the checked-frame marker"),
+            ErrorKind::InvalidFrame,
+        ))
+    }
+}
+
+pub(crate) fn parse_checked_header(header: &[u8; CHECKED_HEADER_LEN]) -> Result<CheckedHeader> {
+    let [m0, m1, m2, m3, flags, l0, l1, l2, l3, c0, c1, c2, c3] = *header;
+    check_magic([m0, m1, m2, m3])?;
+    if flags != FLAG_CRC32 {
+        return Err(Error::with_kind(
+            format!("unsupported frame flags {flags:#04x}"),
+            ErrorKind::InvalidFrame,
+        ));
+    }
+    Ok(CheckedHeader {
+        len: u32::from_be_bytes([l0, l1, l2, l3]),
+        crc32: u32::from_be_bytes([c0, c1, c2, c3]),
+    })
+}
+
+/// Fails with `ErrorKind::InvalidFrame` unless `payload` matches the header's checksum.
+pub(crate) fn verify(header: CheckedHeader, payload: &[u8]) -> Result<()> {
+    let crc32 = crc32(payload);
+    if crc32 == header.crc32 {
+        Ok(())
+    } else {
+        Err(Error::with_kind(
+            format!(
+                "checksum mismatch in {}-byte frame (header {:#010x}, payload {crc32:#010x})",
+                payload.len(),
+                header.crc32
+            ),
+            ErrorKind::InvalidFrame,
+        ))
+    }
+}
+
+/// The marker, flags, length and checksum that precede `payload` in a checked frame.
+pub(crate) fn checked_header(payload: &[u8]) -> Result<[u8; CHECKED_HEADER_LEN]> {
+    let len = u32::try_from(payload.len()).map_err(|_| {
+        Error::with_kind(
+            format!("message too long ({} bytes)", payload.len()),
+            ErrorKind::MessageTooLarge,
+        )
+    })?;
+    let [m0, m1, m2, m3] = MAGIC;
+    let [l0, l1, l2, l3] = len.to_be_bytes();
+    let [c0, c1, c2, c3] = crc32(payload).to_be_bytes();
+    Ok([m0, m1, m2, m3, FLAG_CRC32, l0, l1, l2, l3, c0, c1, c2, c3])
+}
+
+/// Frames `payload` with the marker and checksum.
+pub(crate) fn encode_checked(payload: &[u8]) -> Result<Vec<u8>> {
+    let mut frame = Vec::with_capacity(CHECKED_HEADER_LEN + payload.len());
+    frame.extend_from_slice(&checked_header(payload)?);
+    frame.extend_from_slice(payload);
+    Ok(frame)
+}
+
+/// Returns the payload of a single complete checked frame.
+pub(crate) fn decode_checked(frame: &[u8]) -> Result<&[u8]> {
+    let Some((header, payload)) = frame.split_first_chunk::<CHECKED_HEADER_LEN>() else {
+        return Err(Error::with_kind(
+            format!("frame of {} bytes is shorter than its header", frame.len()),
+            ErrorKind::InvalidFrame,
+        ));
+    };
+    let header = parse_checked_header(header)?;
+    if header.len as usize != payload.len() {
+        return Err(Error::with_kind(
+            format!(
+                "frame declares {} payload bytes but carries {}",
+                header.len,
+                payload.len()
+            ),
+            ErrorKind::InvalidFrame,
+        ));
+    }
+    verify(header, payload)?;
+    Ok(payload)
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 as used by zlib and Ethernet (reflected polynomial 0xedb88320).
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        CRC32_TABLE[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_header_rejects_oversized_message() {
+        assert_eq!(frame_header(5).unwrap(), 5u32.to_be_bytes());
+        let error = frame_header(u32::MAX as usize + 1).unwrap_err();
+        assert!(error.to_string().contains("message too long"));
+        assert_eq!(error.kind(), ErrorKind::MessageTooLarge);
+    }
+
+    #[test]
+    fn test_decode_rejects_mismatched_length() {
+        assert_eq!(decode(&encode(b"hello").unwrap()).unwrap(), b"hello");
+        assert_eq!(decode(&[0, 0]).unwrap_err().kind(), ErrorKind::InvalidFrame);
+        assert!(decode(&[0, 0, 0, 5, b'h']).is_err());
+        assert!(decode(&[0, 0, 0, 0, b'h']).is_err());
+    }
+
+    #[test]
+    fn test_plain_lengths_never_look_checked() {
+        assert!(!is_checked(
+            frame_header(MAX_PLAIN_LEN as usize - 1).unwrap()
+        ));
+        assert!(frame_header(MAX_PLAIN_LEN as usize).is_err());
+    }
+
+    #[test]
+    fn test_crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_checked_round_trip_and_corruption() {
+        let frame = encode_checked(b"hello").unwrap();
+        assert_eq!(frame.len(), CHECKED_HEADER_LEN + 5);
+        assert_eq!(decode_checked(&frame).unwrap(), b"hello");
+
+        let mut corrupted = frame.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        let error = decode_checked(&corrupted).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidFrame);
+        assert!(error.to_string().contains("checksum mismatch"), "{error}");
+
+        let mut unknown_flags = frame;
+        unknown_flags[MAGIC.len()] = 0x80;
+        let error = decode_checked(&unknown_flags).unwrap_err();
+        assert!(
+            error.to_string().contains("unsupported frame flags"),
+            "{error}"
+        );
+    }
+}
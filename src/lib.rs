@@ -1,241 +1,806 @@
-use std::{
-    ffi::CString,
-    os::{
-        fd::AsRawFd,
-        unix::{ffi::OsStrExt, io::RawFd},
-    },
-    path::Path,
-};
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+#![cfg_attr(test, allow(clippy::unwrap_used, clippy::expect_used))]
 
-use self::{errno::Errno, error::*};
+#[cfg(all(not(unix), not(feature = "unsupported-stubs")))]
+compile_error!(
+    "quipe requires a Unix target; enable the `unsupported-stubs` feature to build stub types elsewhere"
+);
 
+pub mod audit;
+#[cfg(feature = "serde")]
+pub mod codec;
+pub mod conformance;
+#[cfg(unix)]
 mod errno;
 mod error;
+mod frame;
+mod options;
+#[cfg(all(unix, feature = "async"))]
+pub mod tokio;
+#[cfg(unix)]
+mod unix;
+#[cfg(all(not(unix), feature = "unsupported-stubs"))]
+mod unsupported;
 
-pub struct PipeQueue {
-    write_fd: RawFd,
-}
+#[cfg(unix)]
+pub use self::audit::audit;
+pub use self::error::{BatchError, Error, ErrorKind, Result};
+pub use self::frame::FrameMeta;
+pub use self::options::{
+    NoReader, Options, QueueOptions, ReaderOptions, SecurityPreset, DEFAULT_MAX_MESSAGE_LEN,
+};
+#[cfg(all(unix, feature = "async"))]
+pub use self::tokio::{AsyncPipeQueue, AsyncPipeReader};
+#[cfg(unix)]
+pub use self::unix::{loopback, Drain, PipeQueue, PipeReader};
+#[cfg(all(not(unix), feature = "unsupported-stubs"))]
+pub use self::unsupported::{audit, loopback, Drain, PipeQueue, PipeReader};
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{
+        io::Write,
+        path::Path,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc, Barrier,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use tempfile::tempdir;
+
+    use super::*;
 
-impl AsRawFd for PipeQueue {
-    fn as_raw_fd(&self) -> RawFd {
-        self.write_fd
+    /// Opens a reader once `PipeQueue::create` or `PipeQueue::create_wait` has made the FIFO.
+    fn open_reader_when_created(path: &Path) -> PipeReader {
+        while !path.exists() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        PipeReader::new(path).unwrap()
     }
-}
 
-pub struct PipeReader {
-    read_fd: RawFd,
-}
+    /// Creates a queue at `path` with one reader attached.
+    fn connected_pair(path: &Path) -> (PipeQueue, PipeReader) {
+        let queue = PipeQueue::create(path).unwrap();
+        (queue, PipeReader::new(path).unwrap())
+    }
 
-fn open(path: &Path, flags: libc::c_int, mode: libc::c_int) -> Result<RawFd> {
-    let fd = unsafe {
-        libc::open(
-            path.as_os_str().as_bytes().as_ptr() as *const i8,
-            flags,
-            mode,
-        )
-    };
-    if fd < 0 {
-        Err(Error::new(format!(
-            "failed to open file at {} [errno={errno}]",
-            path.display(),
-            errno = Errno::latest()
-        )))
-    } else {
-        Ok(fd)
+    #[test]
+    fn test_mainline_scenario() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("my_queue");
+
+        // Spawn reader threads. Neither closes its end until both have received, so the queue
+        // keeps a reader for both sends.
+        let received = Arc::new(Barrier::new(2));
+        let path1 = path.clone();
+        let received1 = received.clone();
+        let handle1 = thread::spawn(move || {
+            let reader1 = open_reader_when_created(&path1);
+            let data = reader1.receive().unwrap();
+            assert!(data == b"Hello, reader!");
+            received1.wait();
+        });
+
+        let path2 = path.clone();
+        let handle2 = thread::spawn(move || {
+            let reader2 = open_reader_when_created(&path2);
+            let data = reader2.receive().unwrap();
+            assert!(data == b"Hello, reader!");
+            received.wait();
+        });
+
+        let queue = PipeQueue::create_wait(&path, Duration::from_secs(10)).unwrap();
+
+        // Send a message
+        queue.send(b"Hello, reader!").unwrap();
+        queue.send(b"Hello, reader!").unwrap();
+
+        handle1.join().unwrap();
+        handle2.join().unwrap();
     }
-}
 
-fn mkfifo(path: &Path, mode: libc::mode_t) -> Result<()> {
-    let result = unsafe {
-        libc::mkfifo(
-            CString::new(path.as_os_str().as_bytes()).unwrap().as_ptr(),
-            mode,
-        )
-    };
-    if result < 0 {
-        Err(Error::new(format!(
-            "failed to create FIFO at {} [errno={errno}]",
-            path.display(),
-            errno = Errno::latest(),
-        )))
-    } else {
-        Ok(())
+    /// FNV-1a, so a frame spliced together from two messages fails verification.
+    fn checksum(data: &[u8]) -> u64 {
+        data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+        })
     }
-}
 
-fn read_all(fd: RawFd, mut data: &mut [u8]) -> Result<()> {
-    while !data.is_empty() {
-        match unsafe { libc::read(fd, data.as_mut_ptr() as *mut libc::c_void, data.len()) } {
-            0 => {
-                return Err(Error::new("failed to read all bytes"));
-            }
-            -1 => {
-                if Errno::latest().is_eagain() {
-                    continue;
-                } else {
-                    return Err(Error::new(format!(
-                        "failed to read [errno={errno}]",
-                        errno = Errno::latest(),
-                    )));
-                }
+    #[test]
+    fn test_large_frames_with_concurrent_readers_and_writers() {
+        const WRITERS: usize = 4;
+        const READERS: usize = 4;
+        const MESSAGES_PER_WRITER: usize = 16;
+        const MESSAGE_LEN: usize = 1 << 20;
+
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("my_queue");
+        // Pairs of writer threads, and of reader threads, share a handle, so both the locks
+        // between handles and the ones within a handle are exercised.
+        let queues = [
+            PipeQueue::create(&path).unwrap(),
+            PipeQueue::options()
+                .create_if_missing(false)
+                .reuse_existing(true)
+                .open(&path)
+                .unwrap(),
+        ];
+        let readers = (0..READERS / 2)
+            .map(|_| PipeReader::new(&path).unwrap())
+            .collect::<Vec<_>>();
+        let received = AtomicUsize::new(0);
+        let total = WRITERS * MESSAGES_PER_WRITER;
+
+        thread::scope(|scope| {
+            for (writer, queue) in (0..WRITERS).zip(queues.iter().cycle()) {
+                scope.spawn(move || {
+                    for i in 0..MESSAGES_PER_WRITER {
+                        // Every message has different contents, followed by their checksum.
+                        let seed = (writer * MESSAGES_PER_WRITER + i) as u8;
+                        let mut message = (0..MESSAGE_LEN - 8)
+                            .map(|j| (j as u8).wrapping_mul(31).wrapping_add(seed))
+                            .collect::<Vec<_>>();
+                        message.extend_from_slice(&checksum(&message).to_be_bytes());
+                        queue.send(&message).unwrap();
+                    }
+                });
             }
-            n => {
-                assert!(n > 0, "undefined behavior from POSIX read!");
-                let n = n as usize;
-                data = &mut data[n..];
+            for reader in readers.iter().chain(&readers) {
+                let received = &received;
+                scope.spawn(move || {
+                    while received.load(Ordering::SeqCst) < total {
+                        let Some(message) =
+                            reader.receive_timeout(Duration::from_millis(10)).unwrap()
+                        else {
+                            continue;
+                        };
+                        assert_eq!(message.len(), MESSAGE_LEN);
+                        let (body, sum) = message.split_at(MESSAGE_LEN - 8);
+                        assert_eq!(checksum(body).to_be_bytes(), sum, "corrupted frame");
+                        received.fetch_add(1, Ordering::SeqCst);
+                    }
+                });
             }
-        }
+        });
+        assert_eq!(received.into_inner(), total);
     }
-    assert!(data.is_empty());
-    Ok(())
-}
 
-fn write_all(fd: RawFd, mut data: &[u8]) -> Result<()> {
-    while !data.is_empty() {
-        match unsafe { libc::write(fd, data.as_ptr() as *const libc::c_void, data.len()) } {
-            0 => {
-                return Err(Error::new("failed to write all bytes [errno={errno}]"));
-            }
-            -1 => {
-                if Errno::latest().is_eagain() {
-                    continue;
-                } else {
-                    return Err(Error::new(format!(
-                        "failed to write [errno={errno}]",
-                        errno = Errno::latest(),
-                    )));
+    extern "C" fn ignore_signal(_signal: libc::c_int) {}
+
+    #[test]
+    fn test_receive_survives_interrupting_signals() {
+        // Without SA_RESTART, a blocked read(2) fails with EINTR when the handler runs.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = ignore_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            assert_eq!(
+                libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut()),
+                0
+            );
+        }
+
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("my_queue");
+        let _queue = PipeQueue::create(&path).unwrap();
+        let reader = PipeReader::new(&path).unwrap();
+        let message = (0..256 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let done = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            let (done, reader) = (&done, &reader);
+            let receiving = scope.spawn(move || {
+                // The signaller is joined before this thread exits, so it never signals a thread
+                // that is gone.
+                let receiving_thread = unsafe { libc::pthread_self() };
+                let signaller = scope.spawn(move || {
+                    while !done.load(Ordering::SeqCst) {
+                        unsafe { libc::pthread_kill(receiving_thread, libc::SIGUSR1) };
+                        thread::sleep(Duration::from_micros(200));
+                    }
+                });
+                let data = reader.receive();
+                done.store(true, Ordering::SeqCst);
+                signaller.join().unwrap();
+                data
+            });
+
+            // Dribble the frame out so the reader keeps blocking between partial reads.
+            let mut writer = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+            writer
+                .write_all(&(message.len() as u32).to_be_bytes())
+                .unwrap();
+            for chunk in message.chunks(4096) {
+                thread::sleep(Duration::from_millis(1));
+                // A reader that gave up would leave the pipe to fill up.
+                if done.load(Ordering::SeqCst) {
+                    break;
                 }
+                writer.write_all(chunk).unwrap();
             }
-            n => {
-                assert!(n > 0, "undefined behavior from POSIX write!");
-                let n = n as usize;
-                data = &data[n..];
-            }
-        }
+            assert_eq!(receiving.join().unwrap().unwrap(), message);
+        });
     }
-    assert!(data.is_empty());
-    Ok(())
-}
 
-struct AdvisoryLock {
-    fd: RawFd,
-}
+    #[test]
+    fn test_receive_with_meta() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("my_queue");
+
+        let reader_path = path.clone();
+        let handle = thread::spawn(move || {
+            let reader = open_reader_when_created(&reader_path);
+            reader.receive_with_meta().unwrap()
+        });
+
+        let _queue = PipeQueue::create_wait(&path, Duration::from_secs(10)).unwrap();
 
-impl AdvisoryLock {
-    fn new(fd: RawFd) -> Self {
-        Self { fd }
+        // Deliver the header, then hold back the payload so the frame is visibly in flight.
+        let mut writer = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        writer.write_all(&5u32.to_be_bytes()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        let payload_sent = Instant::now();
+        writer.write_all(b"hello").unwrap();
+
+        let (meta, data) = handle.join().unwrap();
+        assert_eq!(data, b"hello");
+        assert_eq!(meta.len, 5);
+        // Stamped when the header came in, not when the frame was complete.
+        assert!(meta.arrived.unwrap() < payload_sent);
     }
-}
 
-impl Drop for AdvisoryLock {
-    fn drop(&mut self) {
-        flock(self.fd, libc::LOCK_UN).expect("failed to release lock on pipe");
+    #[test]
+    fn test_receive_with_meta_called_late() {
+        let (queue, reader) = loopback(None).unwrap();
+        queue.send(b"hello").unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        let called = Instant::now();
+        let (meta, data) = reader.receive_with_meta().unwrap();
+        assert_eq!(data, b"hello");
+        // The frame sat in the pipe before the call, but the pipe can't say since when.
+        let arrived = meta.arrived.unwrap();
+        assert!(arrived >= called);
     }
-}
 
-fn flock(fd: RawFd, operation: libc::c_int) -> Result<()> {
-    let result = unsafe { libc::flock(fd, operation) };
-    if result < 0 {
-        Err(Error::new(format!(
-            "failed to acquire lock on pipe [errno={errno}]",
-            errno = Errno::latest(),
-        )))
-    } else {
-        Ok(())
+    #[test]
+    fn test_receive_with_meta_without_timestamps() {
+        let (queue, mut reader) = loopback(None).unwrap();
+        reader.set_timestamps(false);
+        queue.send(b"hello").unwrap();
+
+        let (meta, data) = reader.receive_with_meta().unwrap();
+        assert_eq!(data, b"hello");
+        assert_eq!(meta.len, 5);
+        assert!(meta.arrived.is_none());
     }
-}
-impl PipeQueue {
-    pub fn create(path: &Path) -> Result<Self> {
-        mkfifo(path, libc::S_IRWXU)?;
-        let write_fd = open(path, libc::O_WRONLY, 0)?;
-        Ok(PipeQueue { write_fd })
-    }
-
-    pub fn send(&self, data: &[u8]) -> Result<()> {
-        // First byte is the message length
-        let mut message = Vec::with_capacity(std::mem::size_of::<u32>() + data.len());
-        message.extend_from_slice(
-            &u32::try_from(data.len())
-                .expect("message too long")
-                .to_be_bytes(),
+
+    #[test]
+    fn test_try_receive() {
+        let temp_dir = tempdir().unwrap();
+        let (queue, reader) = connected_pair(&temp_dir.path().join("my_queue"));
+
+        assert_eq!(reader.try_receive().unwrap(), None);
+        queue.send(b"Hello, reader!").unwrap();
+        assert_eq!(reader.try_receive().unwrap().unwrap(), b"Hello, reader!");
+        assert_eq!(reader.try_receive().unwrap(), None);
+    }
+
+    #[test]
+    fn test_receive_timeout_on_empty_pipe() {
+        let temp_dir = tempdir().unwrap();
+        let (_queue, reader) = connected_pair(&temp_dir.path().join("my_queue"));
+
+        let start = Instant::now();
+        assert_eq!(
+            reader.receive_timeout(Duration::from_millis(50)).unwrap(),
+            None
         );
-        message.extend_from_slice(data);
-        write_all(self.write_fd, message.as_slice())
+        assert!(start.elapsed() >= Duration::from_millis(50));
     }
-}
 
-impl PipeReader {
-    pub fn new(path: &Path) -> Result<Self> {
-        let read_fd = open(path, libc::O_RDONLY, 0)?;
-        Ok(PipeReader { read_fd })
+    #[test]
+    fn test_receive_timeout_with_delayed_sender() {
+        let temp_dir = tempdir().unwrap();
+        let (queue, reader) = connected_pair(&temp_dir.path().join("my_queue"));
+
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            queue.send(b"Hello, reader!").unwrap();
+            queue
+        });
+        let data = reader.receive_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(data.unwrap(), b"Hello, reader!");
+        handle.join().unwrap();
     }
 
-    pub fn receive(&self) -> Result<Vec<u8>> {
-        let _advisory_lock = AdvisoryLock::new(self.read_fd);
-        self.read_message()
+    #[test]
+    fn test_receive_timeout_without_deadline() {
+        let (queue, reader) = loopback(None).unwrap();
+        queue.send(b"Hello, reader!").unwrap();
+        let data = reader.receive_timeout(Duration::MAX).unwrap();
+        assert_eq!(data.unwrap(), b"Hello, reader!");
     }
 
-    fn read_message(&self) -> Result<Vec<u8>> {
-        // Read the length.
-        let mut len_buf = [0u8; 4];
-        read_all(self.read_fd, &mut len_buf)?;
-        // Allocate space.
-        let msg_len = u32::from_be_bytes(len_buf);
-        // Read the content.
-        let mut buffer = vec![0u8; msg_len as usize];
-        read_all(self.read_fd, buffer.as_mut_slice())?;
-        Ok(buffer)
+    #[test]
+    fn test_loopback_round_trip() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("my_queue");
+        for path in [Some(path.as_path()), None] {
+            let (queue, reader) = loopback(path).unwrap();
+            let handle = thread::spawn(move || {
+                for i in 0..3 {
+                    queue.send(format!("message {i}").as_bytes()).unwrap();
+                }
+            });
+            for i in 0..3 {
+                assert_eq!(reader.receive().unwrap(), format!("message {i}").as_bytes());
+            }
+            handle.join().unwrap();
+        }
     }
-}
 
-impl Drop for PipeQueue {
-    fn drop(&mut self) {
-        let _ = unsafe { libc::close(self.write_fd) };
+    #[test]
+    fn test_create_wait_times_out_without_reader() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("my_queue");
+
+        let start = Instant::now();
+        let error = PipeQueue::create_wait(&path, Duration::from_millis(50))
+            .err()
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        assert_eq!(
+            std::io::Error::from(error).kind(),
+            std::io::ErrorKind::TimedOut
+        );
+        assert!(!path.exists());
     }
-}
 
-impl Drop for PipeReader {
-    fn drop(&mut self) {
-        let _ = unsafe { libc::close(self.read_fd) };
+    #[test]
+    fn test_create_wait_with_reader() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("my_queue");
+
+        let reader_path = path.clone();
+        let handle = thread::spawn(move || {
+            let reader = open_reader_when_created(&reader_path);
+            reader.receive().unwrap()
+        });
+        let queue = PipeQueue::create_wait(&path, Duration::from_secs(10)).unwrap();
+        queue.send(b"Hello, reader!").unwrap();
+        assert_eq!(handle.join().unwrap(), b"Hello, reader!");
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::thread;
+    #[test]
+    fn test_create_wait_without_deadline() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("my_queue");
 
-    use tempfile::tempdir;
+        let reader_path = path.clone();
+        let handle = thread::spawn(move || {
+            let reader = open_reader_when_created(&reader_path);
+            reader.receive().unwrap()
+        });
+        let queue = PipeQueue::create_wait(&path, Duration::MAX).unwrap();
+        queue.send(b"Hello, reader!").unwrap();
+        assert_eq!(handle.join().unwrap(), b"Hello, reader!");
+    }
 
-    use super::*;
+    #[test]
+    fn test_reopen_existing_queue() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("my_queue");
+
+        // A queue that went away without removing its FIFO, as after a crash.
+        drop(PipeQueue::create(&path).unwrap());
+        let queue = PipeQueue::options()
+            .reuse_existing(true)
+            .open(&path)
+            .unwrap();
+        let reader = PipeReader::new(&path).unwrap();
+        queue.send(b"after restart").unwrap();
+        assert_eq!(reader.receive().unwrap(), b"after restart");
+
+        let error = PipeQueue::options()
+            .create_if_missing(false)
+            .reuse_existing(true)
+            .open(&temp_dir.path().join("missing"))
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+    }
 
     #[test]
-    fn test_mainline_scenario() {
-        let temp_dir = std::path::PathBuf::from("/Users/wbbradley/fifo"); //tempdir().unwrap();
-        let path = temp_dir.join("my_queue");
-        std::fs::create_dir_all(temp_dir).unwrap();
+    fn test_options_reject_regular_file() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("my_queue");
+        std::fs::write(&path, b"not a queue").unwrap();
 
-        let queue = PipeQueue::create(&path).unwrap();
+        let error = PipeQueue::options()
+            .reuse_existing(true)
+            .open(&path)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("is not a FIFO"), "{error}");
+        let error = PipeReader::options()
+            .create_if_missing(true)
+            .open(&path)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("is not a FIFO"), "{error}");
+        assert_eq!(std::fs::read(&path).unwrap(), b"not a queue");
+    }
 
-        // Create multiple readers
-        let reader1 = PipeReader::new(&path).unwrap();
-        let reader2 = PipeReader::new(&path).unwrap();
+    #[test]
+    fn test_options_set_mode_bits() {
+        use std::os::unix::fs::PermissionsExt;
 
-        // Spawn reader threads
-        let handle1 = thread::spawn(move || {
-            let data = reader1.receive().unwrap();
-            assert!(data == b"Hello, reader!");
-        });
+        let temp_dir = tempdir().unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o7777;
 
-        let handle2 = thread::spawn(move || {
-            let data = reader2.receive().unwrap();
-            assert!(data == b"Hello, reader!");
+        let path = temp_dir.path().join("shared");
+        let _queue = PipeQueue::options().mode(0o660).open(&path).unwrap();
+        assert_eq!(mode(&path), 0o660);
+
+        let path = temp_dir.path().join("default");
+        let _queue = PipeQueue::create(&path).unwrap();
+        assert_eq!(mode(&path), 0o700);
+
+        let path = temp_dir.path().join("created_by_reader");
+        let _reader = PipeReader::options()
+            .mode(0o640)
+            .create_if_missing(true)
+            .wait_for_writer(false)
+            .open(&path)
+            .unwrap();
+        assert_eq!(mode(&path), 0o640);
+    }
+
+    #[test]
+    fn test_options_when_no_reader() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("my_queue");
+
+        let error = PipeQueue::options()
+            .if_no_reader(NoReader::Fail)
+            .open(&path)
+            .err()
+            .unwrap();
+        assert_eq!(error.raw_os_error(), Some(libc::ENXIO));
+        assert!(!path.exists());
+
+        let reader_path = path.clone();
+        let handle = thread::spawn(move || {
+            let reader = open_reader_when_created(&reader_path);
+            reader.receive().unwrap()
         });
+        let queue = PipeQueue::options()
+            .if_no_reader(NoReader::Block)
+            .open(&path)
+            .unwrap();
+        queue.send(b"Hello, reader!").unwrap();
+        assert_eq!(handle.join().unwrap(), b"Hello, reader!");
+    }
 
-        // Send a message
+    #[test]
+    fn test_send_without_readers() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("my_queue");
+
+        let queue = PipeQueue::create(&path).unwrap();
+        let error = queue.send(b"too early").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NoReaders);
+
+        let reader = PipeReader::new(&path).unwrap();
         queue.send(b"Hello, reader!").unwrap();
+        assert_eq!(reader.receive().unwrap(), b"Hello, reader!");
+
+        drop(reader);
+        let error = queue.send(b"too late").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NoReaders);
+        assert_eq!(error.raw_os_error(), Some(libc::EPIPE));
+    }
+
+    /// A checked queue with a strict reader, plus a raw writer for injecting broken frames.
+    fn checked_pair(path: &Path) -> (PipeQueue, PipeReader, std::fs::File) {
+        let options = Options { checksum: true };
+        let queue = PipeQueue::create_with_options(path, options).unwrap();
+        let reader = PipeReader::new_with_options(path, options).unwrap();
+        let raw = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+        (queue, reader, raw)
+    }
+
+    #[test]
+    fn test_resync_after_torn_write() {
+        let temp_dir = tempdir().unwrap();
+        let (queue, reader, mut raw) = checked_pair(&temp_dir.path().join("my_queue"));
+
+        // A writer that died after 10 of its 64 payload bytes, followed by intact frames.
+        let torn = frame::encode_checked(&[0x5a; 64]).unwrap();
+        raw.write_all(&torn[..frame::CHECKED_HEADER_LEN + 10])
+            .unwrap();
+        queue.send(&[0xa5; 100]).unwrap();
         queue.send(b"Hello, reader!").unwrap();
 
-        handle1.join().unwrap();
-        handle2.join().unwrap();
+        // The torn frame's checksum covers bytes of the next frame, so it fails verification.
+        let error = reader.receive().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidFrame);
+        assert_eq!(reader.resync().unwrap(), [0xa5; 100]);
+        assert_eq!(reader.receive().unwrap(), b"Hello, reader!");
+    }
+
+    #[test]
+    fn test_resync_after_stray_bytes() {
+        let temp_dir = tempdir().unwrap();
+        let (queue, reader, mut raw) = checked_pair(&temp_dir.path().join("my_queue"));
+
+        // The rest of a frame whose header another reader consumed before crashing, including a
+        // false marker.
+        raw.write_all(b"ody bytes \xffQP and more").unwrap();
+        queue.send(b"Hello, reader!").unwrap();
+
+        let error = reader.receive().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidFrame);
+        assert_eq!(reader.resync().unwrap(), b"Hello, reader!");
+    }
+
+    #[test]
+    fn test_plain_reader_accepts_checked_frames() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("my_queue");
+        let queue = PipeQueue::create_with_options(&path, Options { checksum: true }).unwrap();
+        let reader = PipeReader::new(&path).unwrap();
+        queue.send(b"Hello, reader!").unwrap();
+        assert_eq!(reader.receive().unwrap(), b"Hello, reader!");
+    }
+
+    #[test]
+    fn test_send_rejects_oversized_message() {
+        let (mut queue, reader) = loopback(None).unwrap();
+        assert_eq!(queue.max_message_len(), DEFAULT_MAX_MESSAGE_LEN);
+        queue.set_max_message_len(16);
+
+        let error = queue.send(&[0; 17]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::MessageTooLarge);
+        assert!(error.to_string().contains("17-byte message"), "{error}");
+        queue.send(&[1; 16]).unwrap();
+        assert_eq!(reader.receive().unwrap(), [1; 16]);
+    }
+
+    #[test]
+    fn test_receive_rejects_bogus_length() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("my_queue");
+        let (queue, reader) = connected_pair(&path);
+        let mut writer = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+
+        // A corrupt header announcing 2 GiB, followed by a few bytes that never add up to it.
+        writer.write_all(&0x7fff_ffffu32.to_be_bytes()).unwrap();
+        writer.write_all(b"junk").unwrap();
+        let error = reader.receive().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::MessageTooLarge);
+        assert!(error.to_string().contains("2147483647"), "{error}");
+        assert!(error.to_string().contains("discarded 4 bytes"), "{error}");
+
+        queue.send(b"Hello, reader!").unwrap();
+        assert_eq!(reader.receive().unwrap(), b"Hello, reader!");
+    }
+
+    #[test]
+    fn test_receive_discards_oversized_frame() {
+        let (queue, mut reader) = loopback(None).unwrap();
+        reader.set_max_message_len(1024);
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                queue.send(&vec![7; 256 << 10]).unwrap();
+                queue.send(b"next").unwrap();
+            });
+            let error = reader.receive().unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::MessageTooLarge);
+            assert!(error.to_string().contains("discarded it"), "{error}");
+            assert_eq!(reader.receive().unwrap(), b"next");
+        });
+    }
+
+    #[test]
+    fn test_empty_message_round_trip() {
+        let temp_dir = tempdir().unwrap();
+        for (name, checksum) in [("plain", false), ("checked", true)] {
+            let path = temp_dir.path().join(name);
+            let queue = PipeQueue::create_with_options(&path, Options { checksum }).unwrap();
+            let reader = PipeReader::new_with_options(&path, Options { checksum }).unwrap();
+            queue.send(b"").unwrap();
+            queue.send(b"after").unwrap();
+            assert_eq!(reader.receive().unwrap(), Vec::<u8>::new());
+            let mut buffer = b"stale".to_vec();
+            assert_eq!(reader.receive_into(&mut buffer).unwrap(), 5);
+            assert_eq!(buffer, b"after");
+        }
+    }
+
+    #[test]
+    fn test_send_batch_and_drain() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("my_queue");
+        let (queue, reader) = connected_pair(&path);
+        let other_reader = PipeReader::new(&path).unwrap();
+
+        let messages = (0..1000).map(|i| format!("event {i}")).collect::<Vec<_>>();
+        assert_eq!(queue.send_batch(&messages).unwrap(), 1000);
+
+        let mut drain = reader.drain();
+        assert_eq!(drain.next().unwrap().unwrap(), b"event 0");
+        // The drain keeps the lock until it is done.
+        assert_eq!(other_reader.try_receive().unwrap(), None);
+        let rest = drain.collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(rest.len(), 999);
+        for (received, sent) in rest.iter().zip(&messages[1..]) {
+            assert_eq!(received, sent.as_bytes());
+        }
+        assert_eq!(reader.drain().count(), 0);
+
+        queue.send(b"last").unwrap();
+        drop(queue);
+        assert_eq!(
+            reader.drain().collect::<Result<Vec<_>>>().unwrap(),
+            [b"last"]
+        );
+        let error = reader.drain().next().unwrap().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Disconnected);
+    }
+
+    #[test]
+    fn test_send_batch_keeps_frames_whole() {
+        let (mut queue, reader) = loopback(None).unwrap();
+        let large = vec![9; 3 * 4096];
+        let batch: Vec<&[u8]> = vec![b"small", &large, b"", b"after"];
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for expected in &batch {
+                    assert_eq!(&reader.receive().unwrap(), expected);
+                }
+            });
+            assert_eq!(queue.send_batch(&batch).unwrap(), 4);
+        });
+
+        // A message that can't be sent ends the batch after the ones before it.
+        queue.set_max_message_len(8);
+        let error = queue
+            .send_batch([&b"fits"[..], b"too large", b"never"])
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::MessageTooLarge);
+        assert_eq!(error.sent, 1);
+        assert_eq!(reader.receive().unwrap(), b"fits");
+        assert_eq!(reader.try_receive().unwrap(), None);
+    }
+
+    #[test]
+    fn test_send_batch_reports_sent_count_on_failure() {
+        let (queue, reader) = loopback(None).unwrap();
+        let large = vec![9; 1 << 20];
+        let batch: Vec<&[u8]> = vec![b"small", &large, b"never"];
+        // The reader leaves after the first message, while the large one is still going out.
+        let handle = thread::spawn(move || reader.receive().unwrap());
+        let error = queue.send_batch(&batch).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NoReaders);
+        assert_eq!(error.sent, 1);
+        assert_eq!(handle.join().unwrap(), b"small");
+    }
+
+    #[test]
+    fn test_send_batch_with_checked_frames() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("my_queue");
+        let queue = PipeQueue::create_with_options(&path, Options { checksum: true }).unwrap();
+        let reader = PipeReader::new_with_options(&path, Options { checksum: true }).unwrap();
+        assert_eq!(queue.send_batch(["one", "two", "three"]).unwrap(), 3);
+        let received = reader.drain().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(received, [&b"one"[..], b"two", b"three"]);
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("my_queue");
+
+        let error = PipeReader::new(&path).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+
+        let _queue = PipeQueue::create(&path).unwrap();
+        let error = PipeQueue::create(&path).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+
+        let (queue, reader) = loopback(None).unwrap();
+        drop(queue);
+        assert_eq!(
+            reader.receive().unwrap_err().kind(),
+            ErrorKind::Disconnected
+        );
+    }
+
+    #[test]
+    fn test_os_errors_convert_to_io_errors() {
+        let temp_dir = tempdir().unwrap();
+        let error = PipeReader::new(&temp_dir.path().join("missing"))
+            .err()
+            .unwrap();
+        assert_eq!(error.raw_os_error(), Some(libc::ENOENT));
+        let io_error = std::io::Error::from(error);
+        assert_eq!(io_error.kind(), std::io::ErrorKind::NotFound);
+        assert_eq!(io_error.raw_os_error(), Some(libc::ENOENT));
+    }
+
+    #[test]
+    fn test_dropping_handles_while_others_send_and_receive() {
+        const ROUNDS: usize = 20;
+        const THREADS: usize = 4;
+
+        /// Message `i` repeats one byte. Every length takes more than one write, so every send
+        /// takes the writer lock: a small frame from another handle could land inside one.
+        fn message(i: usize) -> Vec<u8> {
+            vec![i as u8; len(i)]
+        }
+
+        fn len(i: usize) -> usize {
+            libc::PIPE_BUF + 1 + i % 3 * 5000
+        }
+
+        let temp_dir = tempdir().unwrap();
+        for round in 0..ROUNDS {
+            let path = temp_dir.path().join(format!("my_queue_{round}"));
+            let (queue, reader) = loopback(Some(&path)).unwrap();
+            let (queue, reader) = (Arc::new(queue), Arc::new(reader));
+            thread::scope(|scope| {
+                // Each thread gives up its handle after a different number of messages, while
+                // the others are still using theirs. Even threads share the first handles, odd
+                // ones open their own.
+                for i in 0..THREADS {
+                    let count = (i + 1) * 50;
+                    let (queue, reader) = if i % 2 == 0 {
+                        (queue.clone(), reader.clone())
+                    } else {
+                        let own_queue = PipeQueue::options()
+                            .create_if_missing(false)
+                            .reuse_existing(true)
+                            .open(&path)
+                            .unwrap();
+                        (
+                            Arc::new(own_queue),
+                            Arc::new(PipeReader::new(&path).unwrap()),
+                        )
+                    };
+                    scope.spawn(move || {
+                        for j in 0..count {
+                            if let Err(error) = queue.send(&message(j)) {
+                                // Every reader has gone.
+                                assert_eq!(error.kind(), ErrorKind::NoReaders, "{error}");
+                                break;
+                            }
+                        }
+                    });
+                    scope.spawn(move || {
+                        for _ in 0..count {
+                            match reader.receive() {
+                                Ok(message) => {
+                                    assert_eq!(message.len(), len(message[0] as usize));
+                                    assert!(message.iter().all(|byte| *byte == message[0]));
+                                }
+                                Err(error) => {
+                                    // Every writer has gone.
+                                    assert_eq!(error.kind(), ErrorKind::Disconnected, "{error}");
+                                    break;
+                                }
+                            }
+                        }
+                    });
+                }
+                drop((queue, reader));
+            });
+        }
     }
 }
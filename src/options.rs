@@ -0,0 +1,232 @@
+//! Settings chosen when a queue is created or opened.
+
+use std::time::Duration;
+
+use crate::{PipeQueue, PipeReader};
+
+/// Options for `PipeQueue::create_with_options` and `PipeReader::new_with_options`.
+/// `Options::default()` matches `PipeQueue::create` and `PipeReader::new`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    /// Queues send checked frames: each carries a marker and a CRC-32 of its payload, so readers
+    /// detect torn or foreign data with `ErrorKind::InvalidFrame` and can recover with
+    /// `PipeReader::resync`.
+    ///
+    /// Every reader accepts checked frames. Readers with this option also reject plain ones, so
+    /// leftover bytes from a torn frame are reported instead of being read as a length.
+    pub checksum: bool,
+}
+
+/// Permission bits of a newly created FIFO unless `mode` says otherwise.
+const DEFAULT_MODE: u32 = 0o700;
+
+/// The largest payload queues send and readers accept unless told otherwise: 16 MiB.
+pub const DEFAULT_MAX_MESSAGE_LEN: usize = 16 << 20;
+
+/// What `QueueOptions::open` does when no reader has the FIFO open yet.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NoReader {
+    /// Open the write end anyway. Until a reader attaches, `send` fails with
+    /// `ErrorKind::NoReaders`. This is what `PipeQueue::create` does.
+    #[default]
+    Open,
+    /// Fail with ENXIO (`ErrorKind::Os`), like a non-blocking open(2).
+    Fail,
+    /// Block until a reader attaches, like a blocking open(2).
+    Block,
+    /// Wait up to this long for a reader, then fail with `ErrorKind::TimedOut`. This is what
+    /// `PipeQueue::create_wait` does.
+    WaitFor(Duration),
+}
+
+/// Hardening for queues that other users on the host must not tamper with, applied by
+/// `QueueOptions::preset` and `ReaderOptions::preset`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SecurityPreset {
+    /// Everything `audit` checks for. A FIFO created by `open` gets mode 0600. `open` fails with
+    /// `ErrorKind::PermissionDenied` if the parent directory is group- or world-writable (sticky
+    /// ones like /tmp included) or if the FIFO isn't owned by the current user or is open to
+    /// anyone else. A symlink at the path fails with ELOOP instead of being followed, and the
+    /// descriptor is close-on-exec so child processes don't inherit it.
+    Strict,
+}
+
+/// How to open the write end of a queue, from `PipeQueue::options()`. Like `std::fs::OpenOptions`,
+/// the setters chain and `open` does the work.
+///
+/// The defaults match `PipeQueue::create`: the FIFO must not exist yet, and is created with mode
+/// 0o700 and opened without waiting for a reader.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(not(unix), allow(dead_code))]
+pub struct QueueOptions {
+    pub(crate) options: Options,
+    pub(crate) mode: u32,
+    pub(crate) create_if_missing: bool,
+    pub(crate) reuse_existing: bool,
+    pub(crate) no_reader: NoReader,
+    pub(crate) max_message_len: usize,
+    pub(crate) preset: Option<SecurityPreset>,
+}
+
+impl Default for QueueOptions {
+    fn default() -> Self {
+        Self {
+            options: Options::default(),
+            mode: DEFAULT_MODE,
+            create_if_missing: true,
+            reuse_existing: false,
+            no_reader: NoReader::default(),
+            max_message_len: DEFAULT_MAX_MESSAGE_LEN,
+            preset: None,
+        }
+    }
+}
+
+impl QueueOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// See `Options::checksum`.
+    pub fn checksum(&mut self, checksum: bool) -> &mut Self {
+        self.options.checksum = checksum;
+        self
+    }
+
+    /// Permission bits for a FIFO created by `open`, e.g. 0o660 for a queue shared by a group.
+    /// They are applied as given, regardless of the umask. An existing FIFO keeps its own.
+    pub fn mode(&mut self, mode: u32) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Create the FIFO if nothing is at the path. On by default; without it the FIFO must
+    /// already exist, which needs `reuse_existing`.
+    pub fn create_if_missing(&mut self, create_if_missing: bool) -> &mut Self {
+        self.create_if_missing = create_if_missing;
+        self
+    }
+
+    /// Open a FIFO that is already at the path, e.g. one left behind by a process that crashed,
+    /// instead of failing with `ErrorKind::AlreadyExists`. Anything at the path that isn't a FIFO
+    /// is still rejected.
+    pub fn reuse_existing(&mut self, reuse_existing: bool) -> &mut Self {
+        self.reuse_existing = reuse_existing;
+        self
+    }
+
+    pub fn if_no_reader(&mut self, no_reader: NoReader) -> &mut Self {
+        self.no_reader = no_reader;
+        self
+    }
+
+    /// Larger messages fail to send with `ErrorKind::MessageTooLarge`. Defaults to
+    /// `DEFAULT_MAX_MESSAGE_LEN`.
+    pub fn max_message_len(&mut self, max_message_len: usize) -> &mut Self {
+        self.max_message_len = max_message_len;
+        self
+    }
+
+    /// Applies `preset`, including its mode. A later `mode` still overrides that, but a `Strict`
+    /// open fails on one that lets anyone else in.
+    pub fn preset(&mut self, preset: SecurityPreset) -> &mut Self {
+        self.mode = 0o600;
+        self.preset = Some(preset);
+        self
+    }
+}
+
+/// How to open the read end of a queue, from `PipeReader::options()`.
+///
+/// The defaults match `PipeReader::new`: the FIFO must already exist, and opening it waits for a
+/// writer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(not(unix), allow(dead_code))]
+pub struct ReaderOptions {
+    pub(crate) options: Options,
+    pub(crate) mode: u32,
+    pub(crate) create_if_missing: bool,
+    pub(crate) wait_for_writer: bool,
+    pub(crate) max_message_len: usize,
+    pub(crate) preset: Option<SecurityPreset>,
+    pub(crate) timestamps: bool,
+}
+
+impl Default for ReaderOptions {
+    fn default() -> Self {
+        Self {
+            options: Options::default(),
+            mode: DEFAULT_MODE,
+            create_if_missing: false,
+            wait_for_writer: true,
+            max_message_len: DEFAULT_MAX_MESSAGE_LEN,
+            preset: None,
+            timestamps: true,
+        }
+    }
+}
+
+impl ReaderOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// See `Options::checksum`.
+    pub fn checksum(&mut self, checksum: bool) -> &mut Self {
+        self.options.checksum = checksum;
+        self
+    }
+
+    /// Like `QueueOptions::mode`.
+    pub fn mode(&mut self, mode: u32) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Create the FIFO if nothing is at the path, so the reader can start before the writer.
+    pub fn create_if_missing(&mut self, create_if_missing: bool) -> &mut Self {
+        self.create_if_missing = create_if_missing;
+        self
+    }
+
+    /// Whether opening blocks until a writer has the FIFO open. Without waiting, receives fail
+    /// with `ErrorKind::Disconnected` until a writer attaches.
+    pub fn wait_for_writer(&mut self, wait_for_writer: bool) -> &mut Self {
+        self.wait_for_writer = wait_for_writer;
+        self
+    }
+
+    /// Frames that declare a larger payload fail to receive with `ErrorKind::MessageTooLarge`
+    /// instead of being buffered. Defaults to `DEFAULT_MAX_MESSAGE_LEN`.
+    pub fn max_message_len(&mut self, max_message_len: usize) -> &mut Self {
+        self.max_message_len = max_message_len;
+        self
+    }
+
+    /// Whether `PipeReader::receive_with_meta` reads the clock for `FrameMeta::arrived`. On by
+    /// default; turning it off saves the clock read and a poll(2) per frame.
+    pub fn timestamps(&mut self, timestamps: bool) -> &mut Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    /// Like `QueueOptions::preset`.
+    pub fn preset(&mut self, preset: SecurityPreset) -> &mut Self {
+        self.mode = 0o600;
+        self.preset = Some(preset);
+        self
+    }
+}
+
+impl PipeQueue {
+    pub fn options() -> QueueOptions {
+        QueueOptions::new()
+    }
+}
+
+impl PipeReader {
+    pub fn options() -> ReaderOptions {
+        ReaderOptions::new()
+    }
+}
@@ -0,0 +1,744 @@
+//! `PipeQueue` and `PipeReader` for tokio, behind the `async` feature.
+//!
+//! Both wrap the blocking types' fds in `AsyncFd` and speak the same frames, and async readers take
+//! the same advisory lock as blocking ones, so sync and async endpoints can share a queue in any
+//! combination.
+
+use std::{
+    io,
+    ops::Range,
+    os::fd::{AsFd, AsRawFd, RawFd},
+    path::Path,
+    time::Duration,
+};
+
+use ::tokio::{
+    io::unix::AsyncFd,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::{
+    error::*,
+    frame::{self, CHECKED_HEADER_LEN, HEADER_LEN},
+    options::Options,
+    unix, PipeQueue, PipeReader,
+};
+
+/// How long to wait before trying a reader or writer lock again while another handle holds it.
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+/// The write end of a FIFO, for use within a tokio runtime.
+pub struct AsyncPipeQueue {
+    inner: AsyncFd<PipeQueue>,
+    /// The frame being sent, kept when a `send` is cancelled partway through.
+    pending: Mutex<Pending>,
+}
+
+#[derive(Default)]
+struct Pending {
+    frame: Vec<u8>,
+    written: usize,
+    /// Whether this handle holds the writer lock. It does from the first byte of a frame larger
+    /// than `PIPE_BUF` to the last, across cancelled `send`s.
+    locked: bool,
+}
+
+/// The `Pending` of a `send` in progress. Dropping it, also when the `send` is cancelled, gives
+/// up the writer lock unless part of a frame has been written.
+struct PendingGuard<'a> {
+    queue: &'a AsyncPipeQueue,
+    pending: MutexGuard<'a, Pending>,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        if self.pending.written == 0 {
+            self.queue.unlock(&mut self.pending);
+        }
+    }
+}
+
+impl AsyncPipeQueue {
+    /// Like `PipeQueue::create`. Must be called within a tokio runtime.
+    pub fn create(path: &Path) -> Result<Self> {
+        Self::create_with_options(path, Options::default())
+    }
+
+    pub fn create_with_options(path: &Path, options: Options) -> Result<Self> {
+        Self::from_queue(PipeQueue::create_with_options(path, options)?)
+    }
+
+    /// Switches `queue` to non-blocking mode and registers it with the current runtime.
+    pub fn from_queue(queue: PipeQueue) -> Result<Self> {
+        unix::set_nonblocking(queue.as_raw_fd(), true)?;
+        Ok(Self {
+            inner: AsyncFd::new(queue)?,
+            pending: Mutex::default(),
+        })
+    }
+
+    /// Fails with `ErrorKind::NoReaders` if no reader has the queue open.
+    ///
+    /// Cancel safety: a frame counts as sent once its first byte reaches the pipe. If the future
+    /// is dropped before that, nothing was sent. If it is dropped after, the rest of the frame is
+    /// written before the next frame from this handle, or when the handle is dropped, so readers
+    /// never see a torn frame. Like `PipeQueue::send`, this holds the writer lock shared with
+    /// other handles on the pipe while a frame larger than `PIPE_BUF` goes out, so they wait for
+    /// that frame as well. Smaller frames are written atomically and skip the lock.
+    pub async fn send(&self, data: &[u8]) -> Result<()> {
+        let frame = self.inner.get_ref().encode(data)?;
+        let mut guard = PendingGuard {
+            queue: self,
+            pending: self.pending.lock().await,
+        };
+        if guard.pending.written > 0 {
+            self.write_pending(&mut guard.pending).await?;
+        }
+        if !guard.pending.locked && frame.len() > libc::PIPE_BUF {
+            self.lock().await?;
+            guard.pending.locked = true;
+        }
+        guard.pending.frame = frame;
+        self.write_pending(&mut guard.pending).await
+    }
+
+    async fn write_pending(&self, pending: &mut Pending) -> Result<()> {
+        let fd = self.inner.as_raw_fd();
+        while pending.written < pending.frame.len() {
+            let mut ready = self.inner.writable().await?;
+            let rest = &pending.frame[pending.written..];
+            let result = match ready.try_io(|_| {
+                unix::without_sigpipe(|| unix::try_write(fd, rest)).map_err(io::Error::from)
+            }) {
+                Ok(result) => result,
+                Err(_would_block) => continue,
+            };
+            match result {
+                Ok(0) => {
+                    pending.clear();
+                    return Err(Error::new("failed to write all bytes"));
+                }
+                Ok(n) => pending.written += n,
+                Err(error) => {
+                    pending.clear();
+                    return Err(error.into());
+                }
+            }
+        }
+        pending.clear();
+        Ok(())
+    }
+
+    /// Takes the writer lock shared with blocking queues. Waiting for it can't be awaited, so this
+    /// retries a non-blocking attempt every millisecond.
+    async fn lock(&self) -> Result<()> {
+        loop {
+            match unix::lock_writers(self.inner.as_raw_fd(), false) {
+                Err(error) if error.kind() == ErrorKind::WouldBlock => {
+                    ::tokio::time::sleep(LOCK_RETRY_INTERVAL).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn unlock(&self, pending: &mut Pending) {
+        if !std::mem::take(&mut pending.locked) {
+            return;
+        }
+        // The kernel releases the lock when the fd is closed anyway.
+        #[allow(unused_variables)]
+        if let Err(error) = unix::unlock_writers(self.inner.as_raw_fd()) {
+            #[cfg(feature = "log")]
+            log::warn!("failed to release writer lock on pipe: {error}");
+        }
+    }
+}
+
+impl Pending {
+    /// Forgets the frame, but not the lock.
+    fn clear(&mut self) {
+        self.frame = Vec::new();
+        self.written = 0;
+    }
+}
+
+impl Drop for AsyncPipeQueue {
+    /// Finishes a frame left behind by a cancelled `send`. The pipe can stay full for as long as
+    /// its readers take, so that happens on tokio's blocking pool instead of a runtime worker, or
+    /// right here when dropped outside a runtime. The writer lock is released once the frame is
+    /// done. A runtime that is shutting down may never run the blocking task, and then the frame
+    /// is left torn.
+    fn drop(&mut self) {
+        let mut pending = std::mem::take(self.pending.get_mut());
+        if pending.written == 0 {
+            self.unlock(&mut pending);
+            return;
+        }
+        // The duplicate shares the open file description, and with it the writer lock, so other
+        // writers keep waiting after this handle's own fd is closed.
+        match self.inner.get_ref().as_fd().try_clone_to_owned() {
+            Ok(fd) => {
+                let finish = move || finish_frame(fd.as_raw_fd(), &pending);
+                match ::tokio::runtime::Handle::try_current() {
+                    Ok(runtime) => drop(runtime.spawn_blocking(finish)),
+                    Err(_) => finish(),
+                }
+            }
+            Err(_) => finish_frame(self.inner.as_raw_fd(), &pending),
+        }
+    }
+}
+
+/// Writes the rest of `pending`, blocking while the pipe is full, then releases the writer lock.
+fn finish_frame(fd: RawFd, pending: &Pending) {
+    let rest = &pending.frame[pending.written..];
+    #[allow(unused_variables)]
+    if let Err(error) = unix::without_sigpipe(|| unix::write_all(fd, rest)) {
+        #[cfg(feature = "log")]
+        log::warn!("failed to finish a cancelled frame: {error}");
+    }
+    if !pending.locked {
+        return;
+    }
+    #[allow(unused_variables)]
+    if let Err(error) = unix::unlock_writers(fd) {
+        #[cfg(feature = "log")]
+        log::warn!("failed to release writer lock on pipe: {error}");
+    }
+}
+
+/// The read end of a FIFO, for use within a tokio runtime.
+pub struct AsyncPipeReader {
+    inner: AsyncFd<PipeReader>,
+    /// The frame being received, kept when a `receive` is cancelled partway through.
+    partial: Mutex<Partial>,
+}
+
+#[derive(Default)]
+struct Partial {
+    /// Everything read of the frame so far, header included.
+    bytes: Vec<u8>,
+    /// Whether this handle holds the reader lock. It does from the first byte of a frame to
+    /// the last, across cancelled `receive`s.
+    locked: bool,
+    /// The oversized frame whose payload is being read and dropped.
+    discard: Option<Discard>,
+}
+
+struct Discard {
+    /// The payload length the frame declares.
+    len: usize,
+    /// How many of its bytes are still in the pipe.
+    remaining: usize,
+}
+
+impl Partial {
+    /// Whether part of a frame has been read, so the lock has to be kept until it is done.
+    fn in_frame(&self) -> bool {
+        !self.bytes.is_empty() || self.discard.is_some()
+    }
+}
+
+/// The `Partial` of a `receive` in progress. Dropping it, also when the `receive` is cancelled,
+/// gives up the reader lock unless a frame has started arriving.
+struct PartialGuard<'a> {
+    reader: &'a AsyncPipeReader,
+    partial: MutexGuard<'a, Partial>,
+}
+
+impl Drop for PartialGuard<'_> {
+    fn drop(&mut self) {
+        if !self.partial.in_frame() {
+            self.reader.unlock(&mut self.partial);
+        }
+    }
+}
+
+enum Progress {
+    /// The frame is at least this many bytes long, counting what has been read.
+    Need(usize),
+    /// The frame is complete, with its payload at this range.
+    Complete(Range<usize>),
+    /// The frame declares a payload of this many bytes, over the limit. Only its header has
+    /// been read.
+    Oversized(usize),
+}
+
+impl AsyncPipeReader {
+    /// Opens the read end without waiting for a writer. Until a writer attaches, `receive` fails
+    /// with `ErrorKind::Disconnected`. Must be called within a tokio runtime.
+    pub fn new(path: &Path) -> Result<Self> {
+        Self::new_with_options(path, Options::default())
+    }
+
+    /// Like `new`, but with `Options::checksum` only checked frames are accepted.
+    pub fn new_with_options(path: &Path, options: Options) -> Result<Self> {
+        Self::from_reader(
+            PipeReader::options()
+                .checksum(options.checksum)
+                .wait_for_writer(false)
+                .open(path)?,
+        )
+    }
+
+    /// Switches `reader` to non-blocking mode and registers it with the current runtime.
+    pub fn from_reader(reader: PipeReader) -> Result<Self> {
+        unix::set_nonblocking(reader.as_raw_fd(), true)?;
+        Ok(Self {
+            inner: AsyncFd::new(reader)?,
+            partial: Mutex::default(),
+        })
+    }
+
+    /// Receives the next frame, waiting as long as it takes.
+    ///
+    /// Cancel safety: if the future is dropped partway through a frame, the bytes read so far
+    /// stay buffered in this handle, along with the reader lock, and the next `receive` picks the
+    /// frame up where it left off. Other readers wait for that frame to be finished. A `receive`
+    /// dropped before any of a frame arrived releases the lock, so it holds nobody up.
+    ///
+    /// A frame over `PipeReader::max_message_len` fails with `ErrorKind::MessageTooLarge`. Like
+    /// the blocking `receive`, this reads and drops its payload first, so the next receive starts
+    /// at the next frame. A receive cancelled meanwhile leaves the rest to the next one, which
+    /// then fails the same way.
+    pub async fn receive(&self) -> Result<Vec<u8>> {
+        let mut guard = PartialGuard {
+            reader: self,
+            partial: self.partial.lock().await,
+        };
+        let result = self.receive_frame(&mut guard.partial).await;
+        if result.is_err() {
+            // Dropping the guard then releases the lock.
+            guard.partial.bytes.clear();
+            guard.partial.discard = None;
+        }
+        result
+    }
+
+    async fn receive_frame(&self, partial: &mut Partial) -> Result<Vec<u8>> {
+        if !partial.locked {
+            self.lock().await?;
+            partial.locked = true;
+        }
+        let fd = self.inner.as_raw_fd();
+        let checked_only = self.inner.get_ref().checked_only();
+        let max_len = self.inner.get_ref().max_message_len();
+        loop {
+            if partial.discard.is_some() {
+                return Err(self.discard(partial).await?);
+            }
+            let len = match progress(&partial.bytes, checked_only, max_len)? {
+                Progress::Need(len) => len,
+                Progress::Oversized(len) => {
+                    partial.bytes.clear();
+                    partial.discard = Some(Discard {
+                        len,
+                        remaining: len,
+                    });
+                    continue;
+                }
+                Progress::Complete(payload) => {
+                    let mut bytes = std::mem::take(&mut partial.bytes);
+                    self.unlock(partial);
+                    bytes.truncate(payload.end);
+                    bytes.drain(..payload.start);
+                    return Ok(bytes);
+                }
+            };
+            let mut ready = self.inner.readable().await?;
+            let missing = len - partial.bytes.len();
+            let bytes = &mut partial.bytes;
+            // SAFETY: `try_read` reports exactly how many bytes it initialized.
+            match ready.try_io(|_| {
+                unsafe { unix::fill_uninit(bytes, missing, |spare| unix::try_read(fd, spare)) }
+                    .map_err(io::Error::from)
+            }) {
+                Ok(result) => result?,
+                Err(_would_block) => {}
+            }
+        }
+    }
+
+    /// Reads and drops the rest of an oversized payload, keeping track in `partial` so a
+    /// cancelled `receive` can't lose its place, and returns the error for the frame. Like the
+    /// blocking reader, this gives up once no more bytes arrive for `DISCARD_IDLE_TIMEOUT`, since
+    /// a corrupt length can announce bytes that never come.
+    async fn discard(&self, partial: &mut Partial) -> Result<Error> {
+        let fd = self.inner.as_raw_fd();
+        let max_len = self.inner.get_ref().max_message_len();
+        let mut scratch = Vec::<u8>::new();
+        while let Some(discard) = partial
+            .discard
+            .as_mut()
+            .filter(|discard| discard.remaining > 0)
+        {
+            let Ok(ready) =
+                ::tokio::time::timeout(unix::DISCARD_IDLE_TIMEOUT, self.inner.readable()).await
+            else {
+                break;
+            };
+            let mut ready = ready?;
+            scratch.reserve(discard.remaining.min(64 << 10));
+            let chunk = discard.remaining.min(scratch.capacity());
+            match ready.try_io(|_| {
+                unix::try_read(fd, &mut scratch.spare_capacity_mut()[..chunk])
+                    .map_err(io::Error::from)
+            }) {
+                Ok(Ok(0)) => break,
+                Ok(Ok(n)) => discard.remaining -= n,
+                Ok(Err(error)) => return Err(error.into()),
+                Err(_would_block) => {}
+            }
+        }
+        let discard = partial
+            .discard
+            .take()
+            .ok_or_else(|| Error::new("no oversized frame to discard"))?;
+        Ok(unix::oversized_frame(
+            discard.len,
+            max_len,
+            discard.len - discard.remaining,
+        ))
+    }
+
+    /// Takes the advisory lock shared with blocking readers. flock(2) can't be awaited, so this
+    /// retries a non-blocking attempt every millisecond.
+    async fn lock(&self) -> Result<()> {
+        loop {
+            match unix::flock(self.inner.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) {
+                Err(error) if error.kind() == ErrorKind::WouldBlock => {
+                    ::tokio::time::sleep(LOCK_RETRY_INTERVAL).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn unlock(&self, partial: &mut Partial) {
+        if !std::mem::take(&mut partial.locked) {
+            return;
+        }
+        // The frame is already out of the pipe, so failing to unlock shouldn't lose it. The
+        // kernel releases the lock when the fd is closed anyway.
+        #[allow(unused_variables)]
+        if let Err(error) = unix::flock(self.inner.as_raw_fd(), libc::LOCK_UN) {
+            #[cfg(feature = "log")]
+            log::warn!("failed to release lock on pipe: {error}");
+        }
+    }
+}
+
+/// Works out how much of a frame `bytes` holds, verifying it once it is complete.
+fn progress(bytes: &[u8], checked_only: bool, max_len: usize) -> Result<Progress> {
+    let Some(header) = bytes.first_chunk::<HEADER_LEN>() else {
+        return Ok(Progress::Need(HEADER_LEN));
+    };
+    if !checked_only && !frame::is_checked(*header) {
+        let len = frame::payload_len(*header) as usize;
+        if len > max_len {
+            return Ok(Progress::Oversized(len));
+        }
+        let end = HEADER_LEN + len;
+        return Ok(if bytes.len() < end {
+            Progress::Need(end)
+        } else {
+            Progress::Complete(HEADER_LEN..end)
+        });
+    }
+    frame::check_magic(*header)?;
+    let Some(header) = bytes.first_chunk::<CHECKED_HEADER_LEN>() else {
+        return Ok(Progress::Need(CHECKED_HEADER_LEN));
+    };
+    let header = frame::parse_checked_header(header)?;
+    if header.len as usize > max_len {
+        return Ok(Progress::Oversized(header.len as usize));
+    }
+    let end = CHECKED_HEADER_LEN + header.len as usize;
+    if bytes.len() < end {
+        return Ok(Progress::Need(end));
+    }
+    frame::verify(header, &bytes[CHECKED_HEADER_LEN..end])?;
+    Ok(Progress::Complete(CHECKED_HEADER_LEN..end))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeSet,
+        sync::{Arc, Mutex as StdMutex},
+        thread,
+        time::Instant,
+    };
+
+    use ::tokio::time::timeout;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Message `i`: its index, then a length that varies up to several pipe buffers.
+    fn message(i: usize) -> Vec<u8> {
+        let mut message = (i as u64).to_be_bytes().to_vec();
+        message.resize(8 + (i * 7919) % 200_000, i as u8);
+        message
+    }
+
+    fn index(message: &[u8]) -> usize {
+        let index = u64::from_be_bytes(message[..8].try_into().unwrap()) as usize;
+        assert_eq!(message, &self::message(index)[..]);
+        index
+    }
+
+    #[::tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_async_readers_with_sync_writer() {
+        const MESSAGES: usize = 60;
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("queue");
+        let queue = PipeQueue::create(&path).unwrap();
+        let readers: Vec<_> = (0..3)
+            .map(|_| Arc::new(AsyncPipeReader::new(&path).unwrap()))
+            .collect();
+
+        let writer = thread::spawn(move || {
+            for i in 0..MESSAGES {
+                queue.send(&message(i)).unwrap();
+            }
+            // Closing the queue would make idle readers see end-of-file.
+            queue
+        });
+
+        let received = Arc::new(StdMutex::new(BTreeSet::new()));
+        let tasks: Vec<_> = readers
+            .iter()
+            .map(|reader| {
+                let reader = reader.clone();
+                let received = received.clone();
+                ::tokio::spawn(async move {
+                    while received.lock().unwrap().len() < MESSAGES {
+                        // Short timeouts cancel some receives mid-frame.
+                        let Ok(message) = timeout(Duration::from_millis(5), reader.receive()).await
+                        else {
+                            continue;
+                        };
+                        let index = index(&message.unwrap());
+                        assert!(received.lock().unwrap().insert(index), "duplicate {index}");
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            timeout(Duration::from_secs(30), task)
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        writer.join().unwrap();
+        assert_eq!(received.lock().unwrap().len(), MESSAGES);
+    }
+
+    #[::tokio::test(flavor = "multi_thread")]
+    async fn test_sync_reader_with_async_writer() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("queue");
+        let queue = AsyncPipeQueue::create_with_options(&path, Options { checksum: true }).unwrap();
+        let reader = PipeReader::new_with_options(&path, Options { checksum: true }).unwrap();
+
+        let consumer = thread::spawn(move || {
+            (0..20)
+                .map(|_| index(&reader.receive().unwrap()))
+                .collect::<Vec<_>>()
+        });
+        for i in 0..20 {
+            queue.send(&message(i)).await.unwrap();
+        }
+        assert_eq!(consumer.join().unwrap(), (0..20).collect::<Vec<_>>());
+    }
+
+    #[::tokio::test(flavor = "multi_thread")]
+    async fn test_async_and_sync_writers_keep_frames_whole() {
+        const MESSAGES: usize = 40;
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("queue");
+        let queue = PipeQueue::create(&path).unwrap();
+        let async_queue = AsyncPipeQueue::from_queue(
+            PipeQueue::options()
+                .create_if_missing(false)
+                .reuse_existing(true)
+                .open(&path)
+                .unwrap(),
+        )
+        .unwrap();
+        let reader = PipeReader::new(&path).unwrap();
+
+        // Even messages go through the blocking handle, odd ones through the async one.
+        let writer = thread::spawn(move || {
+            for i in (0..MESSAGES).step_by(2) {
+                queue.send(&message(i)).unwrap();
+            }
+            queue
+        });
+        let consumer = thread::spawn(move || {
+            (0..MESSAGES)
+                .map(|_| index(&reader.receive().unwrap()))
+                .collect::<BTreeSet<_>>()
+        });
+        for i in (1..MESSAGES).step_by(2) {
+            async_queue.send(&message(i)).await.unwrap();
+        }
+        assert_eq!(consumer.join().unwrap(), (0..MESSAGES).collect());
+        writer.join().unwrap();
+    }
+
+    #[::tokio::test]
+    async fn test_cancelled_receive_keeps_partial_frame() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("queue");
+        let queue = PipeQueue::create(&path).unwrap();
+        let reader = AsyncPipeReader::new(&path).unwrap();
+
+        let frame = frame::encode(b"hello world").unwrap();
+        let (head, tail) = frame.split_at(HEADER_LEN + 5);
+        unix::write_all(queue.as_raw_fd(), head).unwrap();
+        assert!(timeout(Duration::from_millis(20), reader.receive())
+            .await
+            .is_err());
+        assert!(!reader.partial.lock().await.bytes.is_empty());
+
+        unix::write_all(queue.as_raw_fd(), tail).unwrap();
+        queue.send(b"next").unwrap();
+        assert_eq!(reader.receive().await.unwrap(), b"hello world");
+        assert_eq!(reader.receive().await.unwrap(), b"next");
+    }
+
+    #[::tokio::test]
+    async fn test_cancelled_receive_on_idle_queue_releases_lock() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("queue");
+        let queue = PipeQueue::create(&path).unwrap();
+        let async_reader = AsyncPipeReader::new(&path).unwrap();
+        let reader = PipeReader::new(&path).unwrap();
+
+        assert!(timeout(Duration::from_millis(10), async_reader.receive())
+            .await
+            .is_err());
+        assert!(!async_reader.partial.lock().await.locked);
+        queue.send(b"hello").unwrap();
+        let received = reader.receive_timeout(Duration::from_millis(500)).unwrap();
+        assert_eq!(received.unwrap(), b"hello");
+    }
+
+    #[::tokio::test(flavor = "multi_thread")]
+    async fn test_cancelled_send_finishes_frame() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("queue");
+        let queue = AsyncPipeQueue::create(&path).unwrap();
+        let reader = PipeReader::new(&path).unwrap();
+
+        // Nobody reads yet, so the frame fills the pipe and the send has to be cancelled.
+        let large = vec![7; 1 << 20];
+        assert!(timeout(Duration::from_millis(20), queue.send(&large))
+            .await
+            .is_err());
+        assert!(queue.pending.lock().await.written > 0);
+
+        let consumer =
+            thread::spawn(move || [reader.receive().unwrap(), reader.receive().unwrap()]);
+        queue.send(b"next").await.unwrap();
+        let [first, second] = consumer.join().unwrap();
+        assert_eq!(first, large);
+        assert_eq!(second, b"next");
+    }
+
+    #[::tokio::test(flavor = "multi_thread")]
+    async fn test_drop_finishes_cancelled_frame_off_the_runtime() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("queue");
+        let queue = AsyncPipeQueue::create(&path).unwrap();
+        let reader = PipeReader::new(&path).unwrap();
+
+        let large = vec![7; 1 << 20];
+        assert!(timeout(Duration::from_millis(20), queue.send(&large))
+            .await
+            .is_err());
+
+        // The pipe is still full, but dropping doesn't wait for a reader to make room.
+        let start = Instant::now();
+        drop(queue);
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        let consumer = thread::spawn(move || (reader.receive().unwrap(), reader.receive()));
+        let (first, second) = consumer.join().unwrap();
+        assert_eq!(first, large);
+        assert_eq!(second.unwrap_err().kind(), ErrorKind::Disconnected);
+    }
+
+    #[::tokio::test]
+    async fn test_size_limit_and_empty_message() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("queue");
+        let queue = AsyncPipeQueue::create(&path).unwrap();
+        let mut reader = PipeReader::new(&path).unwrap();
+        reader.set_max_message_len(4);
+        let reader = AsyncPipeReader::from_reader(reader).unwrap();
+
+        queue.send(b"").await.unwrap();
+        assert_eq!(reader.receive().await.unwrap(), b"");
+        queue.send(b"too long").await.unwrap();
+        queue.send(b"next").await.unwrap();
+        let error = reader.receive().await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::MessageTooLarge);
+        assert_eq!(reader.receive().await.unwrap(), b"next");
+    }
+
+    #[::tokio::test(flavor = "multi_thread")]
+    async fn test_oversized_frame_is_discarded() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("queue");
+        let queue = PipeQueue::create(&path).unwrap();
+        let mut reader = PipeReader::new(&path).unwrap();
+        reader.set_max_message_len(1024);
+        let reader = AsyncPipeReader::from_reader(reader).unwrap();
+
+        // Larger than the pipe, so the payload is discarded while it is still being written.
+        let writer = thread::spawn(move || {
+            queue.send(&vec![7; 1 << 20]).unwrap();
+            queue.send(b"next").unwrap();
+            queue
+        });
+        let error = reader.receive().await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::MessageTooLarge);
+        assert!(error.to_string().contains("1048576"), "{error}");
+        assert_eq!(reader.receive().await.unwrap(), b"next");
+        let queue = writer.join().unwrap();
+
+        // A receive cancelled partway through the payload leaves the rest to the next one.
+        let frame = frame::encode(&[7; 2048]).unwrap();
+        let (head, tail) = frame.split_at(HEADER_LEN + 100);
+        unix::write_all(queue.as_raw_fd(), head).unwrap();
+        assert!(timeout(Duration::from_millis(20), reader.receive())
+            .await
+            .is_err());
+        assert!(reader.partial.lock().await.discard.is_some());
+        unix::write_all(queue.as_raw_fd(), tail).unwrap();
+        queue.send(b"after").unwrap();
+        let error = reader.receive().await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::MessageTooLarge);
+        assert!(error.to_string().contains("discarded it"), "{error}");
+        assert_eq!(reader.receive().await.unwrap(), b"after");
+    }
+
+    #[::tokio::test]
+    async fn test_checked_reader_rejects_corrupt_frame() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("queue");
+        let queue = PipeQueue::create(&path).unwrap();
+        let reader = AsyncPipeReader::new_with_options(&path, Options { checksum: true }).unwrap();
+
+        let mut corrupted = frame::encode_checked(b"hello").unwrap();
+        *corrupted.last_mut().unwrap() ^= 1;
+        unix::write_all(queue.as_raw_fd(), &corrupted).unwrap();
+        let error = reader.receive().await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidFrame);
+        assert!(!reader.partial.lock().await.locked);
+    }
+}
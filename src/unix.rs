@@ -0,0 +1,1776 @@
+use std::{
+    ffi::CString,
+    io::IoSlice,
+    mem::MaybeUninit,
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd},
+        unix::{ffi::OsStrExt, io::RawFd},
+    },
+    path::Path,
+    sync::{Mutex, MutexGuard, PoisonError, TryLockError},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    errno::Errno,
+    error::*,
+    frame::{self, FrameMeta},
+    options::{
+        NoReader, Options, QueueOptions, ReaderOptions, SecurityPreset, DEFAULT_MAX_MESSAGE_LEN,
+    },
+};
+
+/// The write end of a FIFO.
+///
+/// Each handle exclusively owns its file descriptor and closes it on drop. Sharing a handle
+/// between threads goes through `&PipeQueue` (e.g. via `Arc`), so the descriptor can only be
+/// closed once no thread is inside `send`.
+///
+/// The raw descriptor is only borrowed through `AsFd` and `AsRawFd`: it is valid while the handle
+/// lives, and a copy kept past that may name whatever file gets the number next. `FromRawFd`
+/// takes ownership of a descriptor and `IntoRawFd` gives it up, so no two handles ever close the
+/// same one. A second handle should open the FIFO again rather than wrap a dup(2) of this
+/// descriptor: duplicates share their open file description, and with it the locks that keep
+/// frames whole. An `AsyncPipeQueue` owns its `PipeQueue` the same way and closes it when dropped;
+/// a frame that a cancelled `send` left unfinished is completed in the background first, through
+/// a duplicate that keeps the writer lock.
+///
+/// Writes to a pipe are only atomic up to `PIPE_BUF` bytes, so `send`s take turns: threads sharing
+/// a handle through a mutex, and separate handles on the pipe through a writer lock for frames
+/// larger than `PIPE_BUF`. On Linux and Android that lock also covers handles in other processes;
+/// elsewhere it only covers this one. Frames that fit in `PIPE_BUF` go out in one atomic write
+/// and skip the lock, which saves its syscalls, but that also means a small frame from another
+/// handle, or from a writer that bypasses this crate, can land in the middle of a large one.
+/// Handles that mix sizes on one pipe can detect that with `Options::checksum`.
+pub struct PipeQueue {
+    write_fd: OwnedFd,
+    send_lock: Mutex<()>,
+    checksum: bool,
+    max_message_len: usize,
+}
+
+/// The read end of a FIFO.
+///
+/// Like `PipeQueue`, each handle exclusively owns its file descriptor, so dropping a reader can
+/// never race with a `receive` on the same handle. Raw descriptor access follows the same rules.
+/// An `AsyncPipeReader` closes its reader when it is dropped; if a cancelled `receive` stopped
+/// partway through a frame, the rest of that frame stays in the pipe.
+///
+/// Readers take turns reading whole frames: separate handles through an advisory lock on the
+/// pipe, and threads sharing one handle through a mutex, since the lock is held per handle.
+pub struct PipeReader {
+    read_fd: OwnedFd,
+    /// Also keeps the bytes of the last invalid frame, for `resync` to scan again.
+    receive_lock: Mutex<Vec<u8>>,
+    checksum: bool,
+    max_message_len: usize,
+    timestamps: bool,
+}
+
+impl PipeQueue {
+    fn from_fd(write_fd: OwnedFd) -> Self {
+        Self {
+            write_fd,
+            send_lock: Mutex::new(()),
+            checksum: false,
+            max_message_len: DEFAULT_MAX_MESSAGE_LEN,
+        }
+    }
+}
+
+impl PipeReader {
+    fn from_fd(read_fd: OwnedFd) -> Self {
+        Self {
+            read_fd,
+            receive_lock: Mutex::new(Vec::new()),
+            checksum: false,
+            max_message_len: DEFAULT_MAX_MESSAGE_LEN,
+            timestamps: true,
+        }
+    }
+}
+
+fn c_path(path: &Path) -> Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| {
+        Error::with_kind(
+            format!("path {} contains an interior NUL byte", path.display()),
+            ErrorKind::InvalidInput,
+        )
+    })
+}
+
+fn open(path: &Path, flags: libc::c_int, mode: libc::c_int) -> Result<OwnedFd> {
+    let c_path = c_path(path)?;
+    loop {
+        let fd = unsafe { libc::open(c_path.as_ptr(), flags, mode) };
+        if fd >= 0 {
+            // SAFETY: open(2) just returned this fd, so nothing else owns it.
+            return Ok(unsafe { OwnedFd::from_raw_fd(fd) });
+        }
+        let errno = Errno::latest();
+        // A blocking open of a FIFO waits for the other end and can be interrupted by a signal.
+        if !errno.is_eintr() {
+            return Err(Error::with_os_error(
+                format!("failed to open file at {} [errno={errno}]", path.display()),
+                errno.raw(),
+            ));
+        }
+    }
+}
+
+pub(crate) fn mkfifo(path: &Path, mode: libc::mode_t) -> Result<()> {
+    let c_path = c_path(path)?;
+    let result = unsafe { libc::mkfifo(c_path.as_ptr(), mode) };
+    if result < 0 {
+        let errno = Errno::latest();
+        Err(Error::with_os_error(
+            format!(
+                "failed to create FIFO at {} [errno={errno}]",
+                path.display()
+            ),
+            errno.raw(),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// How long to sleep between attempts when a wait has no blocking syscall that honors a timeout.
+const RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+fn pipe() -> Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0 as RawFd; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+        let errno = Errno::latest();
+        Err(Error::with_os_error(
+            format!("failed to create pipe [errno={errno}]"),
+            errno.raw(),
+        ))
+    } else {
+        // SAFETY: pipe(2) just returned these fds, so nothing else owns them.
+        Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+    }
+}
+
+pub(crate) fn set_nonblocking(fd: RawFd, nonblocking: bool) -> Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    let result = if flags < 0 {
+        flags
+    } else if nonblocking {
+        unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) }
+    } else {
+        unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) }
+    };
+    if result < 0 {
+        let errno = Errno::latest();
+        Err(Error::with_os_error(
+            format!("failed to update file status flags [errno={errno}]"),
+            errno.raw(),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// One read(2) into `buffer`, retrying reads interrupted by a signal. Returns how many bytes at
+/// the start of `buffer` it initialized, or 0 once every writer has closed the pipe. Fails with
+/// `ErrorKind::WouldBlock` if a non-blocking fd has nothing to read yet.
+pub(crate) fn try_read(fd: RawFd, buffer: &mut [MaybeUninit<u8>]) -> Result<usize> {
+    loop {
+        match unsafe { libc::read(fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) } {
+            -1 => {
+                let errno = Errno::latest();
+                if !errno.is_eintr() {
+                    return Err(Error::with_os_error(
+                        format!("failed to read [errno={errno}]"),
+                        errno.raw(),
+                    ));
+                }
+            }
+            n if n < 0 => {
+                return Err(Error::new(format!(
+                    "undefined behavior from POSIX read ({n})"
+                )));
+            }
+            n => return Ok(n as usize),
+        }
+    }
+}
+
+/// Like `try_read`, but waits in poll(2) while the fd has nothing to read yet.
+fn read_once(fd: RawFd, buffer: &mut [MaybeUninit<u8>]) -> Result<usize> {
+    loop {
+        match try_read(fd, buffer) {
+            Err(error) if error.kind() == ErrorKind::WouldBlock => {
+                poll(fd, libc::POLLIN, None)?;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[track_caller]
+fn disconnected() -> Error {
+    Error::with_kind(
+        "failed to read all bytes: every writer closed the queue",
+        ErrorKind::Disconnected,
+    )
+}
+
+fn read_all(fd: RawFd, mut data: &mut [u8]) -> Result<()> {
+    while !data.is_empty() {
+        // SAFETY: `MaybeUninit<u8>` has the layout of `u8`, and read(2) only ever stores
+        // initialized bytes, so `data` stays initialized.
+        let uninit = unsafe { &mut *(data as *mut [u8] as *mut [MaybeUninit<u8>]) };
+        match read_once(fd, uninit)? {
+            0 => return Err(disconnected()),
+            n => data = &mut data[n..],
+        }
+    }
+    Ok(())
+}
+
+/// Appends exactly `len` bytes read from `fd` to `buffer`. Unlike `read_all` into zeroed space,
+/// the new bytes are never initialized first.
+fn extend_from_fd(fd: RawFd, buffer: &mut Vec<u8>, len: usize) -> Result<()> {
+    // SAFETY: `read_once` reports exactly how many bytes it initialized.
+    unsafe { fill_uninit(buffer, len, |spare| read_once(fd, spare)) }
+}
+
+/// Appends exactly `len` bytes to `buffer`, reading them straight into its spare capacity.
+///
+/// `read_into` is handed the uninitialized region that is still missing and returns how many bytes
+/// at its start it initialized, or 0 if no more are coming. The buffer's length only ever grows
+/// over bytes reported that way, so it never covers uninitialized memory, and an error leaves it
+/// holding exactly the bytes read so far.
+///
+/// # Safety
+///
+/// Whenever `read_into` returns `Ok(n)`, the first `n` bytes of the slice it was handed must have
+/// been initialized.
+pub(crate) unsafe fn fill_uninit(
+    buffer: &mut Vec<u8>,
+    len: usize,
+    mut read_into: impl FnMut(&mut [MaybeUninit<u8>]) -> Result<usize>,
+) -> Result<()> {
+    let target = buffer
+        .len()
+        .checked_add(len)
+        .ok_or_else(|| Error::new(format!("buffer can't grow by {len} bytes")))?;
+    buffer.reserve_exact(len);
+    while buffer.len() < target {
+        let missing = target - buffer.len();
+        let spare = &mut buffer.spare_capacity_mut()[..missing];
+        match read_into(spare)? {
+            0 => return Err(disconnected()),
+            n if n > missing => {
+                return Err(Error::new(format!(
+                    "read reported {n} bytes into a {missing}-byte buffer"
+                )));
+            }
+            // SAFETY: the first `n` bytes of the spare capacity were initialized, per the
+            // caller's contract, and `n <= missing` keeps the length within the capacity.
+            n => unsafe { buffer.set_len(buffer.len() + n) },
+        }
+    }
+    Ok(())
+}
+
+/// One write(2) of `data`, retrying writes interrupted by a signal. Returns how many bytes were
+/// written. Fails with `ErrorKind::WouldBlock` if a non-blocking fd has no room yet, and with
+/// `ErrorKind::NoReaders` if the pipe has no readers.
+#[cfg_attr(not(feature = "async"), allow(dead_code))]
+pub(crate) fn try_write(fd: RawFd, data: &[u8]) -> Result<usize> {
+    try_write_vectored(fd, &[IoSlice::new(data)])
+}
+
+/// Like `try_write`, but one writev(2) of `buffers` as if they were a single slice. That keeps
+/// its atomicity: up to `PIPE_BUF` bytes in total reach a pipe in one piece.
+fn try_write_vectored(fd: RawFd, buffers: &[IoSlice<'_>]) -> Result<usize> {
+    // IOV_MAX is at least 16 everywhere. Slices beyond that go out on later calls.
+    let count = buffers.len().min(16) as libc::c_int;
+    loop {
+        // SAFETY: `IoSlice` is ABI-compatible with `iovec` on Unix.
+        match unsafe { libc::writev(fd, buffers.as_ptr() as *const libc::iovec, count) } {
+            -1 => {
+                let errno = Errno::latest();
+                if errno.is_eintr() {
+                    // Nothing was written, so retry with the same bytes.
+                    continue;
+                } else if errno.is_epipe() {
+                    return Err(Error::with_os_error(
+                        format!("failed to write: the queue has no readers [errno={errno}]"),
+                        errno.raw(),
+                    ));
+                } else {
+                    return Err(Error::with_os_error(
+                        format!("failed to write [errno={errno}]"),
+                        errno.raw(),
+                    ));
+                }
+            }
+            n if n < 0 => {
+                return Err(Error::new(format!(
+                    "undefined behavior from POSIX write ({n})"
+                )));
+            }
+            n => return Ok(n as usize),
+        }
+    }
+}
+
+pub(crate) fn write_all(fd: RawFd, data: &[u8]) -> Result<()> {
+    write_all_vectored(fd, &mut [IoSlice::new(data)])
+}
+
+/// Writes all of `buffers` in order, waiting in poll(2) whenever the pipe is full.
+fn write_all_vectored(fd: RawFd, mut buffers: &mut [IoSlice<'_>]) -> Result<()> {
+    // Drop empty slices up front, so writing nothing makes no syscall.
+    IoSlice::advance_slices(&mut buffers, 0);
+    while !buffers.is_empty() {
+        match try_write_vectored(fd, buffers) {
+            Ok(0) => return Err(Error::new("failed to write all bytes")),
+            Ok(n) => IoSlice::advance_slices(&mut buffers, n),
+            Err(error) if error.kind() == ErrorKind::WouldBlock => {
+                poll(fd, libc::POLLOUT, None)?;
+            }
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+fn set_signal_mask(how: libc::c_int, set: &libc::sigset_t) -> Result<libc::sigset_t> {
+    let mut old_set = MaybeUninit::<libc::sigset_t>::uninit();
+    // pthread_sigmask returns the error number instead of setting errno.
+    match unsafe { libc::pthread_sigmask(how, set, old_set.as_mut_ptr()) } {
+        0 => Ok(unsafe { old_set.assume_init() }),
+        result => {
+            let errno = Errno::from(result);
+            Err(Error::with_os_error(
+                format!("failed to update signal mask [errno={errno}]"),
+                errno.raw(),
+            ))
+        }
+    }
+}
+
+/// Runs `write` with SIGPIPE blocked on the calling thread, so writing to a FIFO without readers
+/// fails with `ErrorKind::NoReaders` instead of the signal killing the process.
+///
+/// The kernel raises SIGPIPE for the writing thread along with EPIPE. Blocked, it stays pending,
+/// so it is consumed before the previous mask is restored. If the caller had SIGPIPE blocked
+/// already, a pending SIGPIPE is left for them.
+pub(crate) fn without_sigpipe<T>(write: impl FnOnce() -> Result<T>) -> Result<T> {
+    let sigpipe = unsafe {
+        let mut set = MaybeUninit::<libc::sigset_t>::uninit();
+        libc::sigemptyset(set.as_mut_ptr());
+        libc::sigaddset(set.as_mut_ptr(), libc::SIGPIPE);
+        set.assume_init()
+    };
+    let old_mask = set_signal_mask(libc::SIG_BLOCK, &sigpipe)?;
+    let result = write();
+    let already_blocked = unsafe { libc::sigismember(&old_mask, libc::SIGPIPE) } == 1;
+    let failed_with_epipe = matches!(&result, Err(error) if error.kind() == ErrorKind::NoReaders);
+    if failed_with_epipe && !already_blocked {
+        let mut pending = MaybeUninit::<libc::sigset_t>::uninit();
+        let pending = unsafe {
+            libc::sigpending(pending.as_mut_ptr());
+            pending.assume_init()
+        };
+        if unsafe { libc::sigismember(&pending, libc::SIGPIPE) } == 1 {
+            // Returns immediately, since the signal is already pending.
+            let mut signal = 0;
+            unsafe { libc::sigwait(&sigpipe, &mut signal) };
+        }
+    }
+    set_signal_mask(libc::SIG_SETMASK, &old_mask)?;
+    result
+}
+
+/// Exclusive access to the read end for one frame, among all readers of the pipe.
+struct AdvisoryLock<'a> {
+    fd: RawFd,
+    discarded: MutexGuard<'a, Vec<u8>>,
+}
+
+impl<'a> AdvisoryLock<'a> {
+    /// Blocks until this reader holds the exclusive lock on the pipe.
+    fn acquire(reader: &'a PipeReader) -> Result<Self> {
+        // The mutex only guards bytes kept for `resync`, which a panic can't have made unsafe to
+        // scan.
+        let guard = reader
+            .receive_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        flock(reader.read_fd.as_raw_fd(), libc::LOCK_EX)?;
+        Ok(Self {
+            fd: reader.read_fd.as_raw_fd(),
+            discarded: guard,
+        })
+    }
+
+    /// Returns `None` instead of blocking if another reader holds the lock.
+    fn try_acquire(reader: &'a PipeReader) -> Result<Option<Self>> {
+        let guard = match reader.receive_lock.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(TryLockError::WouldBlock) => return Ok(None),
+        };
+        match flock(reader.read_fd.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) {
+            Ok(()) => Ok(Some(Self {
+                fd: reader.read_fd.as_raw_fd(),
+                discarded: guard,
+            })),
+            Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Returns `None` if the lock is still held by another reader at `deadline`. Neither flock(2)
+    /// nor the mutex has a timeout, so this retries a non-blocking attempt every millisecond.
+    fn acquire_until(reader: &'a PipeReader, deadline: Instant) -> Result<Option<Self>> {
+        loop {
+            if let Some(lock) = Self::try_acquire(reader)? {
+                return Ok(Some(lock));
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            std::thread::sleep(remaining.min(RETRY_INTERVAL));
+        }
+    }
+}
+
+impl Drop for AdvisoryLock<'_> {
+    fn drop(&mut self) {
+        // Drop can't report failure. The kernel releases the lock when the fd is closed anyway.
+        #[allow(unused_variables)]
+        if let Err(error) = flock(self.fd, libc::LOCK_UN) {
+            #[cfg(feature = "log")]
+            log::warn!("failed to release lock on pipe: {error}");
+        }
+    }
+}
+
+/// Exclusive access to the write end for one frame, or one batch of them, among all writer
+/// handles of the pipe. Threads sharing a handle take turns through its `send_lock` first.
+struct WriterLock {
+    fd: RawFd,
+}
+
+impl WriterLock {
+    /// Blocks until this handle holds the writer lock on the pipe.
+    fn acquire(queue: &PipeQueue) -> Result<Self> {
+        let fd = queue.write_fd.as_raw_fd();
+        lock_writers(fd, true)?;
+        Ok(Self { fd })
+    }
+}
+
+impl Drop for WriterLock {
+    fn drop(&mut self) {
+        // Drop can't report failure. The kernel releases the lock when the fd is closed anyway.
+        #[allow(unused_variables)]
+        if let Err(error) = unlock_writers(self.fd) {
+            #[cfg(feature = "log")]
+            log::warn!("failed to release writer lock on pipe: {error}");
+        }
+    }
+}
+
+/// Takes the lock that writer handles hold while a frame is going out, so frames larger than
+/// `PIPE_BUF` from different handles can't interleave. Without `wait`, fails with
+/// `ErrorKind::WouldBlock` if another handle holds it.
+///
+/// Readers already lock the pipe with flock(2), and writers can't share that lock: a writer holds
+/// its lock while the pipe is full, so a reader waiting for the same lock would never empty it.
+/// On Linux, writers lock the first byte of the pipe with an open file description lock instead,
+/// which flock(2) doesn't see and which, unlike a classic fcntl(2) lock, keeps two handles in one
+/// process apart.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn lock_writers(fd: RawFd, wait: bool) -> Result<()> {
+    let command = if wait {
+        libc::F_OFD_SETLKW
+    } else {
+        libc::F_OFD_SETLK
+    };
+    fcntl_lock(fd, libc::F_WRLCK, command)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn unlock_writers(fd: RawFd) -> Result<()> {
+    fcntl_lock(fd, libc::F_UNLCK, libc::F_OFD_SETLK)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn fcntl_lock(fd: RawFd, lock_type: libc::c_int, command: libc::c_int) -> Result<()> {
+    // SAFETY: all zeroes is a valid `flock`, and open file description locks require `l_pid` to
+    // be 0.
+    let mut lock = unsafe { MaybeUninit::<libc::flock>::zeroed().assume_init() };
+    lock.l_type = lock_type as libc::c_short;
+    lock.l_whence = libc::SEEK_SET as libc::c_short;
+    lock.l_start = 0;
+    lock.l_len = 1;
+    loop {
+        if unsafe { libc::fcntl(fd, command, &lock) } == 0 {
+            return Ok(());
+        }
+        let errno = Errno::latest();
+        // A blocking lock waits for other writers and can be interrupted by a signal.
+        if !errno.is_eintr() {
+            return Err(Error::with_os_error(
+                format!("failed to update writer lock on pipe [errno={errno}]"),
+                errno.raw(),
+            ));
+        }
+    }
+}
+
+/// Other targets have no lock that is both per handle and independent of flock(2), so writer
+/// handles are only kept apart within this process, by the pipes they have locked. Frames larger
+/// than `PIPE_BUF` from writers in different processes can still interleave there.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+static LOCKED_PIPES: Mutex<Vec<(libc::dev_t, libc::ino_t)>> = Mutex::new(Vec::new());
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+static PIPE_UNLOCKED: std::sync::Condvar = std::sync::Condvar::new();
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn lock_writers(fd: RawFd, wait: bool) -> Result<()> {
+    let pipe = pipe_id(fd)?;
+    let mut locked = LOCKED_PIPES.lock().unwrap_or_else(PoisonError::into_inner);
+    while locked.contains(&pipe) {
+        if !wait {
+            return Err(Error::with_kind(
+                "another handle holds the writer lock on the pipe",
+                ErrorKind::WouldBlock,
+            ));
+        }
+        locked = PIPE_UNLOCKED
+            .wait(locked)
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+    locked.push(pipe);
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn unlock_writers(fd: RawFd) -> Result<()> {
+    let pipe = pipe_id(fd)?;
+    let mut locked = LOCKED_PIPES.lock().unwrap_or_else(PoisonError::into_inner);
+    locked.retain(|locked| *locked != pipe);
+    PIPE_UNLOCKED.notify_all();
+    Ok(())
+}
+
+/// The device and inode of the pipe behind `fd`, which every handle on it shares.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn pipe_id(fd: RawFd) -> Result<(libc::dev_t, libc::ino_t)> {
+    let mut stat = MaybeUninit::<libc::stat>::uninit();
+    if unsafe { libc::fstat(fd, stat.as_mut_ptr()) } < 0 {
+        let errno = Errno::latest();
+        return Err(Error::with_os_error(
+            format!("failed to stat fd {fd} [errno={errno}]"),
+            errno.raw(),
+        ));
+    }
+    let stat = unsafe { stat.assume_init() };
+    Ok((stat.st_dev, stat.st_ino))
+}
+
+/// Waits until `deadline` (or forever if it is `None`) for `events` on `fd`. Returns whether the
+/// fd became ready. POLLHUP and POLLERR count as ready, since the next syscall will report them
+/// without blocking. A wait interrupted by a signal resumes with the time that is left.
+fn poll(fd: RawFd, events: libc::c_short, deadline: Option<Instant>) -> Result<bool> {
+    poll_events(fd, events, deadline).map(|revents| revents != 0)
+}
+
+/// Like `poll`, but returns the events that occurred, or none if `deadline` passed first.
+fn poll_events(
+    fd: RawFd,
+    events: libc::c_short,
+    deadline: Option<Instant>,
+) -> Result<libc::c_short> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events,
+        revents: 0,
+    };
+    loop {
+        let timeout_ms = deadline.map_or(-1, remaining_ms);
+        match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+            -1 => {
+                let errno = Errno::latest();
+                if !errno.is_eintr() {
+                    return Err(Error::with_os_error(
+                        format!("failed to poll pipe [errno={errno}]"),
+                        errno.raw(),
+                    ));
+                }
+            }
+            0 => return Ok(0),
+            _ => return Ok(pollfd.revents),
+        }
+    }
+}
+
+/// Milliseconds left until `deadline`, rounded up so a wait never ends early.
+fn remaining_ms(deadline: Instant) -> libc::c_int {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    libc::c_int::try_from(remaining.as_nanos().div_ceil(1_000_000)).unwrap_or(libc::c_int::MAX)
+}
+
+pub(crate) fn flock(fd: RawFd, operation: libc::c_int) -> Result<()> {
+    loop {
+        if unsafe { libc::flock(fd, operation) } == 0 {
+            return Ok(());
+        }
+        let errno = Errno::latest();
+        // A blocking LOCK_EX waits for other readers and can be interrupted by a signal.
+        if !errno.is_eintr() {
+            return Err(Error::with_os_error(
+                format!("failed to acquire lock on pipe [errno={errno}]"),
+                errno.raw(),
+            ));
+        }
+    }
+}
+
+fn stat(path: &Path) -> Result<libc::stat> {
+    let c_path = c_path(path)?;
+    let mut stat = MaybeUninit::<libc::stat>::uninit();
+    if unsafe { libc::stat(c_path.as_ptr(), stat.as_mut_ptr()) } < 0 {
+        let errno = Errno::latest();
+        return Err(Error::with_os_error(
+            format!("failed to stat {} [errno={errno}]", path.display()),
+            errno.raw(),
+        ));
+    }
+    Ok(unsafe { stat.assume_init() })
+}
+
+fn chmod(path: &Path, mode: libc::mode_t) -> Result<()> {
+    let c_path = c_path(path)?;
+    if unsafe { libc::chmod(c_path.as_ptr(), mode) } < 0 {
+        let errno = Errno::latest();
+        Err(Error::with_os_error(
+            format!(
+                "failed to set mode {mode:o} on {} [errno={errno}]",
+                path.display()
+            ),
+            errno.raw(),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Makes sure there is a FIFO at `path`, creating or reusing one as allowed. Returns whether it
+/// was created here, so that it can be removed again if opening it fails.
+fn prepare_fifo(
+    path: &Path,
+    mode: u32,
+    create_if_missing: bool,
+    reuse_existing: bool,
+) -> Result<bool> {
+    if create_if_missing {
+        match mkfifo(path, mode as libc::mode_t) {
+            Ok(()) => {
+                // mkfifo(2) applies the umask, so set the bits that were asked for.
+                if let Err(error) = chmod(path, mode as libc::mode_t) {
+                    let _ = std::fs::remove_file(path);
+                    return Err(error);
+                }
+                return Ok(true);
+            }
+            Err(error) if reuse_existing && error.kind() == ErrorKind::AlreadyExists => {}
+            Err(error) => return Err(error),
+        }
+    } else if !reuse_existing {
+        return Err(Error::new(format!(
+            "nothing to open at {}: neither creating nor reusing a FIFO is allowed",
+            path.display()
+        )));
+    }
+    let mode = stat(path)?.st_mode;
+    if mode & libc::S_IFMT != libc::S_IFIFO {
+        return Err(Error::new(format!(
+            "{} exists but is not a FIFO (mode {mode:o})",
+            path.display()
+        )));
+    }
+    Ok(false)
+}
+
+/// Extra open(2) flags for `preset`.
+fn preset_flags(preset: Option<SecurityPreset>) -> libc::c_int {
+    match preset {
+        Some(SecurityPreset::Strict) => libc::O_CLOEXEC | libc::O_NOFOLLOW,
+        None => 0,
+    }
+}
+
+/// Fails if other users could create or replace entries in the directory `path` is in. Checked
+/// before anything is created there.
+fn check_parent(path: &Path) -> Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mode = stat(parent)?.st_mode & 0o7777;
+    if mode & 0o022 != 0 {
+        return Err(Error::with_kind(
+            format!(
+                "{} is in {}, which is group- or world-writable (mode {mode:04o})",
+                path.display(),
+                parent.display()
+            ),
+            ErrorKind::PermissionDenied,
+        ));
+    }
+    Ok(())
+}
+
+/// Fails unless the FIFO behind `fd` belongs to the current user and nobody else can open it.
+fn check_owner(fd: &OwnedFd, path: &Path) -> Result<()> {
+    let mut stat = MaybeUninit::<libc::stat>::uninit();
+    if unsafe { libc::fstat(fd.as_raw_fd(), stat.as_mut_ptr()) } < 0 {
+        let errno = Errno::latest();
+        return Err(Error::with_os_error(
+            format!("failed to stat {} [errno={errno}]", path.display()),
+            errno.raw(),
+        ));
+    }
+    let stat = unsafe { stat.assume_init() };
+    let uid = unsafe { libc::getuid() };
+    if stat.st_uid != uid {
+        return Err(Error::with_kind(
+            format!(
+                "{} is owned by uid {}, not the current uid {uid}",
+                path.display(),
+                stat.st_uid
+            ),
+            ErrorKind::PermissionDenied,
+        ));
+    }
+    let mode = stat.st_mode & 0o777;
+    if mode & 0o077 != 0 {
+        return Err(Error::with_kind(
+            format!(
+                "{} is open to other users (mode {mode:04o})",
+                path.display()
+            ),
+            ErrorKind::PermissionDenied,
+        ));
+    }
+    Ok(())
+}
+
+/// Opens the write end without blocking. Returns `None` while no reader has the FIFO open.
+fn try_open_write_end(path: &Path, flags: libc::c_int) -> Result<Option<OwnedFd>> {
+    // A non-blocking open of the write end fails with ENXIO while there is no reader.
+    match open(path, libc::O_WRONLY | libc::O_NONBLOCK | flags, 0) {
+        Ok(write_fd) => {
+            set_nonblocking(write_fd.as_raw_fd(), false)?;
+            Ok(Some(write_fd))
+        }
+        Err(error) if error.kind() == ErrorKind::Os(libc::ENXIO) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Opens the write end as `no_reader` says, with `flags` added to every open(2).
+fn open_write_end(path: &Path, no_reader: NoReader, flags: libc::c_int) -> Result<OwnedFd> {
+    let timeout = match no_reader {
+        NoReader::Open => {
+            // Opening the write end blocks, or fails with ENXIO if non-blocking, while there is
+            // no reader, so hold a read end open just long enough to open it.
+            let placeholder_reader = open(path, libc::O_RDONLY | libc::O_NONBLOCK | flags, 0)?;
+            let write_fd = open(path, libc::O_WRONLY | flags, 0);
+            drop(placeholder_reader);
+            return write_fd;
+        }
+        NoReader::Block => return open(path, libc::O_WRONLY | flags, 0),
+        NoReader::Fail => {
+            return try_open_write_end(path, flags)?.ok_or_else(|| {
+                Error::with_os_error(
+                    format!("no reader has {} open", path.display()),
+                    libc::ENXIO,
+                )
+            });
+        }
+        NoReader::WaitFor(timeout) => timeout,
+    };
+    let Some(deadline) = Instant::now().checked_add(timeout) else {
+        // A wait too long to have a deadline is as good as blocking.
+        return open(path, libc::O_WRONLY | flags, 0);
+    };
+    loop {
+        if let Some(write_fd) = try_open_write_end(path, flags)? {
+            return Ok(write_fd);
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(Error::with_kind(
+                format!(
+                    "no reader attached to {} within {timeout:?}",
+                    path.display()
+                ),
+                ErrorKind::TimedOut,
+            ));
+        }
+        std::thread::sleep(remaining.min(RETRY_INTERVAL));
+    }
+}
+
+impl QueueOptions {
+    /// Opens the write end of the FIFO at `path`. If the FIFO was created here and opening it
+    /// fails (e.g. no reader attached in time), it is removed again.
+    pub fn open(&self, path: &Path) -> Result<PipeQueue> {
+        if self.preset.is_some() {
+            check_parent(path)?;
+        }
+        let created = prepare_fifo(path, self.mode, self.create_if_missing, self.reuse_existing)?;
+        let write_fd =
+            open_write_end(path, self.no_reader, preset_flags(self.preset)).and_then(|write_fd| {
+                if self.preset.is_some() {
+                    check_owner(&write_fd, path)?;
+                }
+                Ok(write_fd)
+            });
+        match write_fd {
+            Ok(write_fd) => {
+                let mut queue = PipeQueue::from_fd(write_fd);
+                queue.checksum = self.options.checksum;
+                queue.max_message_len = self.max_message_len;
+                Ok(queue)
+            }
+            Err(error) => {
+                if created {
+                    let _ = std::fs::remove_file(path);
+                }
+                Err(error)
+            }
+        }
+    }
+}
+
+impl ReaderOptions {
+    /// Opens the read end of the FIFO at `path`. If the FIFO was created here and opening it
+    /// fails, it is removed again.
+    pub fn open(&self, path: &Path) -> Result<PipeReader> {
+        if self.preset.is_some() {
+            check_parent(path)?;
+        }
+        let created = prepare_fifo(path, self.mode, self.create_if_missing, true)?;
+        let flags = preset_flags(self.preset);
+        let read_fd = if self.wait_for_writer {
+            open(path, libc::O_RDONLY | flags, 0)
+        } else {
+            // Only the open is non-blocking; receives still wait for data as usual.
+            open(path, libc::O_RDONLY | libc::O_NONBLOCK | flags, 0).and_then(|read_fd| {
+                set_nonblocking(read_fd.as_raw_fd(), false)?;
+                Ok(read_fd)
+            })
+        };
+        let read_fd = read_fd.and_then(|read_fd| {
+            if self.preset.is_some() {
+                check_owner(&read_fd, path)?;
+            }
+            Ok(read_fd)
+        });
+        match read_fd {
+            Ok(read_fd) => {
+                let mut reader = PipeReader::from_fd(read_fd);
+                reader.checksum = self.options.checksum;
+                reader.max_message_len = self.max_message_len;
+                reader.timestamps = self.timestamps;
+                Ok(reader)
+            }
+            Err(error) => {
+                if created {
+                    let _ = std::fs::remove_file(path);
+                }
+                Err(error)
+            }
+        }
+    }
+}
+
+/// Creates both ends of a queue in this process, e.g. so one thread can wake another. With `None`
+/// the queue is an anonymous pipe(2) instead of a FIFO at a path.
+///
+/// `PipeReader::new` blocks until a writer has the FIFO open, and a queue without readers can't
+/// accept messages, so neither end can simply be opened first on one thread. This opens the read
+/// end first without waiting for a writer. The two handles then behave exactly as if they lived in
+/// different processes: once the queue is dropped the reader drains what was sent and then fails,
+/// and further readers can still attach to a FIFO at `path`.
+///
+/// Like `QueueOptions::open`, if the FIFO was created but opening it fails, it is removed again.
+pub fn loopback(path: Option<&Path>) -> Result<(PipeQueue, PipeReader)> {
+    let Some(path) = path else {
+        let (read_fd, write_fd) = pipe()?;
+        return Ok((PipeQueue::from_fd(write_fd), PipeReader::from_fd(read_fd)));
+    };
+    mkfifo(path, libc::S_IRWXU)?;
+    let opened = open(path, libc::O_RDONLY | libc::O_NONBLOCK, 0).and_then(|read_fd| {
+        let reader = PipeReader::from_fd(read_fd);
+        let queue = PipeQueue::from_fd(open(path, libc::O_WRONLY, 0)?);
+        set_nonblocking(reader.read_fd.as_raw_fd(), false)?;
+        Ok((queue, reader))
+    });
+    if opened.is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+    opened
+}
+
+impl PipeQueue {
+    /// Creates the FIFO at `path` and opens its write end without waiting for a reader. Until a
+    /// reader attaches (and whenever every reader has gone), `send` fails with
+    /// `ErrorKind::NoReaders`; use `create_wait` to wait for the first reader instead.
+    ///
+    /// `PipeQueue::options()` also sets the FIFO's mode and can reuse an existing FIFO.
+    pub fn create(path: &Path) -> Result<Self> {
+        Self::create_with_options(path, Options::default())
+    }
+
+    /// Like `create`, with the framing chosen by `options`.
+    pub fn create_with_options(path: &Path, options: Options) -> Result<Self> {
+        Self::options().checksum(options.checksum).open(path)
+    }
+
+    /// Like `create`, but waits up to `timeout` for a reader to attach, so the first `send` can't
+    /// fail for lack of one. On timeout the FIFO is removed again.
+    pub fn create_wait(path: &Path, timeout: Duration) -> Result<Self> {
+        Self::options()
+            .if_no_reader(NoReader::WaitFor(timeout))
+            .open(path)
+    }
+
+    /// Frames `data` the way this queue sends it.
+    #[cfg_attr(not(feature = "async"), allow(dead_code))]
+    pub(crate) fn encode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        frame::check_len(data.len(), self.max_message_len)?;
+        if self.checksum {
+            frame::encode_checked(data)
+        } else {
+            frame::encode(data)
+        }
+    }
+
+    /// The largest payload `send` accepts.
+    pub fn max_message_len(&self) -> usize {
+        self.max_message_len
+    }
+
+    /// See `QueueOptions::max_message_len`.
+    pub fn set_max_message_len(&mut self, max_message_len: usize) {
+        self.max_message_len = max_message_len;
+    }
+
+    /// Fails with `ErrorKind::NoReaders` if no reader has the queue open, and with
+    /// `ErrorKind::MessageTooLarge` if `data` is over `max_message_len`. An empty `data` is a
+    /// valid message.
+    ///
+    /// The header and `data` go out in one writev(2), so the payload is never copied, and a frame
+    /// of up to `PIPE_BUF` bytes still reaches the pipe atomically. Only larger frames take the
+    /// writer lock.
+    pub fn send(&self, data: &[u8]) -> Result<()> {
+        let mut header = [0; frame::CHECKED_HEADER_LEN];
+        let header = self.header(data, &mut header)?;
+        let _guard = self
+            .send_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let _writers = if header.len() + data.len() > libc::PIPE_BUF {
+            Some(WriterLock::acquire(self)?)
+        } else {
+            None
+        };
+        without_sigpipe(|| {
+            write_all_vectored(
+                self.write_fd.as_raw_fd(),
+                &mut [IoSlice::new(header), IoSlice::new(data)],
+            )
+        })
+    }
+
+    /// Sends every message in `messages`, in order, and returns how many were sent. Frames are
+    /// packed into writes of up to `PIPE_BUF` bytes, so a burst of small messages takes a few
+    /// syscalls instead of one each. Only a frame larger than `PIPE_BUF` is split across writes,
+    /// and from the first such frame to the end of the batch the writer lock is held, so large
+    /// frames are torn by no other `send` that takes it.
+    ///
+    /// A message that can't be framed (e.g. one over `max_message_len`), or a failed write, ends
+    /// the batch. The `BatchError` says how many messages were written in full before that, so
+    /// the rest can be sent again.
+    pub fn send_batch<I>(&self, messages: I) -> std::result::Result<usize, BatchError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let _guard = self
+            .send_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut sent = 0;
+        without_sigpipe(|| self.write_batch(messages, &mut sent))
+            .map(|()| sent)
+            .map_err(|error| BatchError { sent, error })
+    }
+
+    /// Counts the messages written in full in `sent` as it goes.
+    fn write_batch<I>(&self, messages: I, sent: &mut usize) -> Result<()>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let fd = self.write_fd.as_raw_fd();
+        let mut chunk = Vec::with_capacity(libc::PIPE_BUF);
+        // How many messages `chunk` holds. It is written in one piece or not at all, since it
+        // fits in `PIPE_BUF`.
+        let mut chunked = 0;
+        // Taken before the first frame that needs it, and held until the batch is done.
+        let mut writers = None;
+        let mut header = [0; frame::CHECKED_HEADER_LEN];
+        for message in messages {
+            let data = message.as_ref();
+            let header = match self.header(data, &mut header) {
+                Ok(header) => header,
+                Err(error) => {
+                    write_all(fd, &chunk)?;
+                    *sent += chunked;
+                    return Err(error);
+                }
+            };
+            let frame_len = header.len() + data.len();
+            if chunk.len() + frame_len > libc::PIPE_BUF {
+                write_all(fd, &chunk)?;
+                *sent += std::mem::take(&mut chunked);
+                chunk.clear();
+            }
+            if frame_len > libc::PIPE_BUF {
+                if writers.is_none() {
+                    writers = Some(WriterLock::acquire(self)?);
+                }
+                write_all_vectored(fd, &mut [IoSlice::new(header), IoSlice::new(data)])?;
+                *sent += 1;
+            } else {
+                chunk.extend_from_slice(header);
+                chunk.extend_from_slice(data);
+                chunked += 1;
+            }
+        }
+        write_all(fd, &chunk)?;
+        *sent += chunked;
+        Ok(())
+    }
+
+    /// Checks that `data` can be sent and builds the header that precedes it in `storage`.
+    fn header<'a>(
+        &self,
+        data: &[u8],
+        storage: &'a mut [u8; frame::CHECKED_HEADER_LEN],
+    ) -> Result<&'a [u8]> {
+        frame::check_len(data.len(), self.max_message_len)?;
+        if self.checksum {
+            *storage = frame::checked_header(data)?;
+            Ok(storage)
+        } else {
+            let header = &mut storage[..frame::HEADER_LEN];
+            header.copy_from_slice(&frame::frame_header(data.len())?);
+            Ok(header)
+        }
+    }
+}
+
+impl PipeReader {
+    pub fn new(path: &Path) -> Result<Self> {
+        Self::new_with_options(path, Options::default())
+    }
+
+    /// Like `new`, but with `Options::checksum` only checked frames are accepted.
+    pub fn new_with_options(path: &Path, options: Options) -> Result<Self> {
+        Self::options().checksum(options.checksum).open(path)
+    }
+
+    /// The largest payload a frame may declare.
+    pub fn max_message_len(&self) -> usize {
+        self.max_message_len
+    }
+
+    /// See `ReaderOptions::max_message_len`.
+    pub fn set_max_message_len(&mut self, max_message_len: usize) {
+        self.max_message_len = max_message_len;
+    }
+
+    /// See `ReaderOptions::timestamps`.
+    pub fn set_timestamps(&mut self, timestamps: bool) {
+        self.timestamps = timestamps;
+    }
+
+    /// Whether only checked frames are accepted.
+    #[cfg_attr(not(feature = "async"), allow(dead_code))]
+    pub(crate) fn checked_only(&self) -> bool {
+        self.checksum
+    }
+
+    /// Takes over `fd`, e.g. one received over a Unix socket, after checking that it refers to a
+    /// pipe or FIFO.
+    pub fn from_owned_fd(fd: OwnedFd) -> Result<Self> {
+        let mut stat = MaybeUninit::<libc::stat>::uninit();
+        if unsafe { libc::fstat(fd.as_raw_fd(), stat.as_mut_ptr()) } < 0 {
+            let errno = Errno::latest();
+            return Err(Error::with_os_error(
+                format!("failed to stat fd {} [errno={errno}]", fd.as_raw_fd()),
+                errno.raw(),
+            ));
+        }
+        let mode = unsafe { stat.assume_init() }.st_mode;
+        if mode & libc::S_IFMT != libc::S_IFIFO {
+            return Err(Error::new(format!(
+                "fd {} is not a pipe or FIFO (mode {mode:o})",
+                fd.as_raw_fd()
+            )));
+        }
+        Ok(PipeReader::from_fd(fd))
+    }
+
+    /// A frame that declares more than `max_message_len` bytes fails with
+    /// `ErrorKind::MessageTooLarge`. Its payload is read and dropped as it arrives, so the next
+    /// receive starts at the next frame.
+    pub fn receive(&self) -> Result<Vec<u8>> {
+        let mut lock = AdvisoryLock::acquire(self)?;
+        self.read_message(&mut lock.discarded)
+    }
+
+    /// Like `receive`, but reads the payload into `buffer`, replacing what it held, and returns its
+    /// length. `buffer` only reallocates when a frame outgrows its capacity, so reusing one
+    /// buffer across receives saves an allocation per message. On error its contents are
+    /// unspecified.
+    pub fn receive_into(&self, buffer: &mut Vec<u8>) -> Result<usize> {
+        let mut lock = AdvisoryLock::acquire(self)?;
+        let header = self.read_header(&mut lock.discarded)?;
+        self.read_payload_into(header, &mut lock.discarded, buffer)?;
+        Ok(buffer.len())
+    }
+
+    /// Receives every frame already waiting in the pipe, stopping once there is nothing more to
+    /// read without blocking. The reader lock is held from the first frame until the iterator is
+    /// exhausted or dropped, so other readers can't take frames out from between them.
+    ///
+    /// Like `try_receive`, this yields nothing if another reader is currently receiving, and a
+    /// frame whose first bytes have arrived is read to completion. Every writer having closed the
+    /// queue ends the drain, and only shows up as `ErrorKind::Disconnected` if no frame was
+    /// drained. Iteration stops after an error.
+    pub fn drain(&self) -> Drain<'_> {
+        Drain {
+            reader: self,
+            lock: None,
+            drained: 0,
+            done: false,
+        }
+    }
+
+    /// Returns `Ok(None)` without blocking if no message has started arriving, or if another reader
+    /// is currently receiving. Once the first bytes of a frame are available, this waits for the
+    /// rest of it so the stream is never left mid-frame.
+    pub fn try_receive(&self) -> Result<Option<Vec<u8>>> {
+        let Some(mut lock) = AdvisoryLock::try_acquire(self)? else {
+            return Ok(None);
+        };
+        if !poll(self.read_fd.as_raw_fd(), libc::POLLIN, Some(Instant::now()))? {
+            return Ok(None);
+        }
+        self.read_message(&mut lock.discarded).map(Some)
+    }
+
+    /// Returns `Ok(None)` if no message starts arriving within `timeout`. A message whose first
+    /// bytes arrive before the deadline is always read to completion, even if that takes longer,
+    /// so the stream is never left mid-frame. A timeout too long to have a deadline, like
+    /// `Duration::MAX`, waits as long as `receive` does.
+    pub fn receive_timeout(&self, timeout: Duration) -> Result<Option<Vec<u8>>> {
+        let deadline = Instant::now().checked_add(timeout);
+        let lock = match deadline {
+            Some(deadline) => AdvisoryLock::acquire_until(self, deadline)?,
+            None => Some(AdvisoryLock::acquire(self)?),
+        };
+        let Some(mut lock) = lock else {
+            return Ok(None);
+        };
+        if !poll(self.read_fd.as_raw_fd(), libc::POLLIN, deadline)? {
+            return Ok(None);
+        }
+        self.read_message(&mut lock.discarded).map(Some)
+    }
+
+    /// Like `receive`, but also reports when the frame became readable. See `FrameMeta`.
+    pub fn receive_with_meta(&self) -> Result<(FrameMeta, Vec<u8>)> {
+        let mut lock = AdvisoryLock::acquire(self)?;
+        let arrived = if self.timestamps {
+            // Waiting here instead of in read(2) tells when the first bytes came in.
+            poll(self.read_fd.as_raw_fd(), libc::POLLIN, None)?;
+            Some(Instant::now())
+        } else {
+            None
+        };
+        let buffer = self.read_message(&mut lock.discarded)?;
+        let meta = FrameMeta {
+            arrived,
+            len: buffer.len(),
+        };
+        Ok((meta, buffer))
+    }
+
+    /// After a receive failed with `ErrorKind::InvalidFrame`, skips ahead to the next intact
+    /// checked frame and returns its payload.
+    ///
+    /// The bytes of the invalid frame are scanned again before reading on, so a frame that a torn
+    /// one ran into is still found. Everything before the next frame that verifies is dropped,
+    /// plain frames included, so this is only useful on queues created with `Options::checksum`.
+    /// A marker that turns up inside garbage makes this wait for as many bytes as its length
+    /// claims.
+    pub fn resync(&self) -> Result<Vec<u8>> {
+        let mut lock = AdvisoryLock::acquire(self)?;
+        // The invalid frame's first byte is where it went wrong, so the next frame starts later.
+        skip_to_marker(&mut lock.discarded, 1);
+        next_checked_frame(
+            self.read_fd.as_raw_fd(),
+            &mut lock.discarded,
+            self.max_message_len,
+        )
+    }
+
+    fn read_message(&self, discarded: &mut Vec<u8>) -> Result<Vec<u8>> {
+        let header = self.read_header(discarded)?;
+        self.read_payload(header, discarded)
+    }
+
+    /// Reads a plain or checked header. The bytes of a checked one are kept in `discarded` until
+    /// its payload verifies.
+    fn read_header(&self, discarded: &mut Vec<u8>) -> Result<Header> {
+        discarded.clear();
+        let fd = self.read_fd.as_raw_fd();
+        let mut len_buf = [0u8; frame::HEADER_LEN];
+        read_all(fd, &mut len_buf)?;
+        if !self.checksum && !frame::is_checked(len_buf) {
+            return Ok(Header::Plain(frame::payload_len(len_buf)));
+        }
+        discarded.extend_from_slice(&len_buf);
+        frame::check_magic(len_buf)?;
+        extend_from_fd(fd, discarded, frame::CHECKED_HEADER_LEN - frame::HEADER_LEN)?;
+        let header = discarded
+            .first_chunk::<{ frame::CHECKED_HEADER_LEN }>()
+            .ok_or_else(|| Error::new("checked header was not read in full"))?;
+        Ok(Header::Checked(frame::parse_checked_header(header)?))
+    }
+
+    fn read_payload(&self, header: Header, discarded: &mut Vec<u8>) -> Result<Vec<u8>> {
+        let mut payload = Vec::new();
+        self.read_payload_into(header, discarded, &mut payload)?;
+        Ok(payload)
+    }
+
+    fn read_payload_into(
+        &self,
+        header: Header,
+        discarded: &mut Vec<u8>,
+        payload: &mut Vec<u8>,
+    ) -> Result<()> {
+        let fd = self.read_fd.as_raw_fd();
+        payload.clear();
+        let len = header.payload_len();
+        if len > self.max_message_len {
+            // Reading on without the payload would take its bytes for the next header.
+            let dropped = discard(fd, len)?;
+            if dropped == len {
+                discarded.clear();
+            }
+            return Err(oversized_frame(len, self.max_message_len, dropped));
+        }
+        match header {
+            Header::Plain(len) => extend_from_fd(fd, payload, len as usize),
+            Header::Checked(header) => {
+                extend_from_fd(fd, payload, header.len as usize)?;
+                match frame::verify(header, payload) {
+                    Ok(()) => {
+                        discarded.clear();
+                        Ok(())
+                    }
+                    Err(error) => {
+                        discarded.extend_from_slice(payload);
+                        Err(error)
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The frames waiting in a pipe, from `PipeReader::drain`.
+pub struct Drain<'a> {
+    reader: &'a PipeReader,
+    lock: Option<AdvisoryLock<'a>>,
+    drained: usize,
+    done: bool,
+}
+
+impl Drain<'_> {
+    fn next_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        let lock = match &mut self.lock {
+            Some(lock) => lock,
+            None => match AdvisoryLock::try_acquire(self.reader)? {
+                Some(lock) => self.lock.insert(lock),
+                None => return Ok(None),
+            },
+        };
+        let revents = poll_events(
+            self.reader.read_fd.as_raw_fd(),
+            libc::POLLIN,
+            Some(Instant::now()),
+        )?;
+        if revents & libc::POLLIN == 0 {
+            // A hang-up with nothing left to read: every writer is gone. A later receive reports
+            // that too, so it doesn't need to end a drain that returned frames.
+            if revents & (libc::POLLHUP | libc::POLLERR) != 0 && self.drained == 0 {
+                return Err(disconnected());
+            }
+            return Ok(None);
+        }
+        self.reader.read_message(&mut lock.discarded).map(Some)
+    }
+}
+
+impl Iterator for Drain<'_> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let frame = self.next_frame().transpose();
+        match &frame {
+            Some(Ok(_)) => self.drained += 1,
+            // Let other readers in as soon as this drain is over.
+            _ => {
+                self.done = true;
+                self.lock = None;
+            }
+        }
+        frame
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Header {
+    Plain(u32),
+    Checked(frame::CheckedHeader),
+}
+
+impl Header {
+    fn payload_len(self) -> usize {
+        match self {
+            Header::Plain(len) => len as usize,
+            Header::Checked(header) => header.len as usize,
+        }
+    }
+}
+
+/// How long `discard` waits for more of an oversized payload before giving up.
+pub(crate) const DISCARD_IDLE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The error for a frame that declares `len` payload bytes, over `max_len`, of which `dropped`
+/// were read and discarded.
+#[track_caller]
+pub(crate) fn oversized_frame(len: usize, max_len: usize, dropped: usize) -> Error {
+    let outcome = if dropped == len {
+        "discarded it".to_string()
+    } else {
+        format!("discarded {dropped} bytes before the pipe went quiet")
+    };
+    Error::with_kind(
+        format!("frame declares {len} payload bytes, over the limit of {max_len} bytes; {outcome}"),
+        ErrorKind::MessageTooLarge,
+    )
+}
+
+/// Reads and drops up to `len` bytes of an oversized payload, returning how many it dropped. A
+/// writer that is sending the frame keeps the pipe busy, but a corrupt length can announce bytes
+/// that never come, so this stops once no more arrive within `DISCARD_IDLE_TIMEOUT`.
+fn discard(fd: RawFd, len: usize) -> Result<usize> {
+    let mut scratch = Vec::<u8>::with_capacity(len.min(64 << 10));
+    let mut dropped = 0;
+    while dropped < len {
+        if !poll(
+            fd,
+            libc::POLLIN,
+            Some(Instant::now() + DISCARD_IDLE_TIMEOUT),
+        )? {
+            break;
+        }
+        let chunk = (len - dropped).min(scratch.capacity());
+        match try_read(fd, &mut scratch.spare_capacity_mut()[..chunk]) {
+            Ok(0) => break,
+            Ok(n) => dropped += n,
+            Err(error) if error.kind() == ErrorKind::WouldBlock => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(dropped)
+}
+
+/// Scans `window`, and then `fd`, for the next checked frame that verifies and returns its payload.
+/// `window` keeps the bytes read but not yet ruled out, also when this fails. Headers declaring
+/// more than `max_len` bytes are ruled out without reading the payload.
+fn next_checked_frame(fd: RawFd, window: &mut Vec<u8>, max_len: usize) -> Result<Vec<u8>> {
+    loop {
+        fill_to(fd, window, frame::MAGIC.len())?;
+        if window[..frame::MAGIC.len()] != frame::MAGIC {
+            skip_to_marker(window, 1);
+            continue;
+        }
+        fill_to(fd, window, frame::CHECKED_HEADER_LEN)?;
+        let header = window
+            .first_chunk::<{ frame::CHECKED_HEADER_LEN }>()
+            .and_then(|header| frame::parse_checked_header(header).ok())
+            .filter(|header| header.len as usize <= max_len);
+        let Some(header) = header else {
+            skip_to_marker(window, 1);
+            continue;
+        };
+        fill_to(fd, window, frame::CHECKED_HEADER_LEN + header.len as usize)?;
+        if frame::verify(header, &window[frame::CHECKED_HEADER_LEN..]).is_ok() {
+            window.drain(..frame::CHECKED_HEADER_LEN);
+            return Ok(std::mem::take(window));
+        }
+        skip_to_marker(window, 1);
+    }
+}
+
+/// Drops the bytes before the first possible marker at or after `from`.
+fn skip_to_marker(window: &mut Vec<u8>, from: usize) {
+    let start = window
+        .iter()
+        .skip(from)
+        .position(|byte| *byte == frame::MAGIC[0])
+        .map_or(window.len(), |i| i + from);
+    window.drain(..start);
+}
+
+/// Reads until `window` holds at least `len` bytes.
+fn fill_to(fd: RawFd, window: &mut Vec<u8>, len: usize) -> Result<()> {
+    match len.checked_sub(window.len()) {
+        Some(missing) if missing > 0 => extend_from_fd(fd, window, missing),
+        _ => Ok(()),
+    }
+}
+
+impl AsFd for PipeQueue {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.write_fd.as_fd()
+    }
+}
+
+impl AsRawFd for PipeQueue {
+    fn as_raw_fd(&self) -> RawFd {
+        self.write_fd.as_raw_fd()
+    }
+}
+
+impl FromRawFd for PipeQueue {
+    /// # Safety
+    ///
+    /// `fd` must be an open write end of a pipe or FIFO that nothing else owns.
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self::from_fd(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+}
+
+impl IntoRawFd for PipeQueue {
+    fn into_raw_fd(self) -> RawFd {
+        self.write_fd.into_raw_fd()
+    }
+}
+
+impl AsFd for PipeReader {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.read_fd.as_fd()
+    }
+}
+
+impl AsRawFd for PipeReader {
+    fn as_raw_fd(&self) -> RawFd {
+        self.read_fd.as_raw_fd()
+    }
+}
+
+impl FromRawFd for PipeReader {
+    /// # Safety
+    ///
+    /// `fd` must be an open read end of a pipe or FIFO that nothing else owns. Use
+    /// `PipeReader::from_owned_fd` to have the file type checked.
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self::from_fd(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+}
+
+impl IntoRawFd for PipeReader {
+    fn into_raw_fd(self) -> RawFd {
+        self.read_fd.into_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Builds a queue and two readers without blocking: the queue's read-write fd satisfies the
+    /// readers' wait for a writer.
+    fn queue_with_two_readers(path: &Path) -> (PipeQueue, PipeReader, PipeReader) {
+        mkfifo(path, libc::S_IRWXU).unwrap();
+        let queue = PipeQueue::from_fd(open(path, libc::O_RDWR, 0).unwrap());
+        let reader = PipeReader::new(path).unwrap();
+        let other_reader = PipeReader::new(path).unwrap();
+        (queue, reader, other_reader)
+    }
+
+    #[test]
+    fn test_large_frames_from_separate_writer_handles() {
+        const MESSAGES_PER_WRITER: usize = 8;
+        const MESSAGE_LEN: usize = 1 << 20;
+
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("my_queue");
+        mkfifo(&path, libc::S_IRWXU).unwrap();
+        let reader =
+            PipeReader::from_fd(open(&path, libc::O_RDONLY | libc::O_NONBLOCK, 0).unwrap());
+        set_nonblocking(reader.read_fd.as_raw_fd(), false).unwrap();
+        let queues = [(); 2].map(|()| PipeQueue::from_fd(open(&path, libc::O_WRONLY, 0).unwrap()));
+
+        std::thread::scope(|scope| {
+            for (writer, queue) in queues.iter().enumerate() {
+                scope.spawn(move || {
+                    for i in 0..MESSAGES_PER_WRITER {
+                        let byte = (writer * MESSAGES_PER_WRITER + i) as u8;
+                        queue.send(&vec![byte; MESSAGE_LEN]).unwrap();
+                    }
+                });
+            }
+            // Each message repeats one byte, so one spliced from two frames shows up.
+            for _ in 0..queues.len() * MESSAGES_PER_WRITER {
+                let message = reader.receive().unwrap();
+                assert_eq!(message.len(), MESSAGE_LEN);
+                assert!(message.iter().all(|byte| *byte == message[0]), "torn frame");
+            }
+        });
+    }
+
+    #[test]
+    fn test_small_frames_from_separate_writer_handles_skip_the_lock() {
+        const MESSAGES_PER_WRITER: usize = 1000;
+        const MESSAGE_LEN: usize = libc::PIPE_BUF - frame::HEADER_LEN;
+
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("my_queue");
+        mkfifo(&path, libc::S_IRWXU).unwrap();
+        let reader =
+            PipeReader::from_fd(open(&path, libc::O_RDONLY | libc::O_NONBLOCK, 0).unwrap());
+        set_nonblocking(reader.read_fd.as_raw_fd(), false).unwrap();
+        let queues = [(); 2].map(|()| PipeQueue::from_fd(open(&path, libc::O_WRONLY, 0).unwrap()));
+
+        // While another handle holds the writer lock, a small frame still goes out.
+        let holder = PipeQueue::from_fd(open(&path, libc::O_WRONLY, 0).unwrap());
+        let writers = WriterLock::acquire(&holder).unwrap();
+        let (sent, was_sent) = std::sync::mpsc::channel();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                queues[0].send(&[0; MESSAGE_LEN]).unwrap();
+                sent.send(()).unwrap();
+            });
+            let was_sent = was_sent.recv_timeout(Duration::from_secs(10));
+            drop(writers);
+            assert!(was_sent.is_ok(), "a small frame waited for the writer lock");
+        });
+        assert_eq!(reader.receive().unwrap(), [0; MESSAGE_LEN]);
+
+        std::thread::scope(|scope| {
+            for (writer, queue) in queues.iter().enumerate() {
+                scope.spawn(move || {
+                    for i in 0..MESSAGES_PER_WRITER {
+                        let byte = (writer * MESSAGES_PER_WRITER + i) as u8;
+                        queue.send(&[byte; MESSAGE_LEN]).unwrap();
+                    }
+                });
+            }
+            for _ in 0..queues.len() * MESSAGES_PER_WRITER {
+                let message = reader.receive().unwrap();
+                assert_eq!(message.len(), MESSAGE_LEN);
+                assert!(message.iter().all(|byte| *byte == message[0]), "torn frame");
+            }
+        });
+    }
+
+    #[test]
+    fn test_try_receive_yields_to_a_receiving_reader() {
+        let temp_dir = tempdir().unwrap();
+        let (queue, reader, other_reader) =
+            queue_with_two_readers(&temp_dir.path().join("my_queue"));
+
+        // Hold the lock the way a reader blocked inside `receive` would.
+        let lock = AdvisoryLock::try_acquire(&other_reader).unwrap();
+        assert!(lock.is_some());
+        assert!(AdvisoryLock::try_acquire(&reader).unwrap().is_none());
+        queue.send(b"Hello, reader!").unwrap();
+        assert_eq!(reader.try_receive().unwrap(), None);
+
+        drop(lock);
+        assert_eq!(reader.try_receive().unwrap().unwrap(), b"Hello, reader!");
+    }
+
+    #[test]
+    fn test_interior_nul_path_is_an_error() {
+        let path = Path::new("my\0queue");
+        let error = PipeQueue::create(path).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("interior NUL byte"));
+        let error = PipeReader::new(path).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("interior NUL byte"));
+    }
+
+    /// Runs under Miri (`cargo +nightly miri test fill_uninit`), since it only touches memory.
+    #[test]
+    fn test_fill_uninit_grows_over_initialized_bytes_only() {
+        let source = (0..100u8).collect::<Vec<_>>();
+        let mut offset = 0;
+        let mut buffer = b"head".to_vec();
+        // Hand back at most 7 bytes per call, so the buffer fills over several short reads.
+        let result = unsafe {
+            fill_uninit(&mut buffer, source.len(), |spare| {
+                let n = spare.len().min(7);
+                for (slot, byte) in spare[..n].iter_mut().zip(&source[offset..]) {
+                    slot.write(*byte);
+                }
+                offset += n;
+                Ok(n)
+            })
+        };
+        result.unwrap();
+        assert_eq!(&buffer[..4], b"head");
+        assert_eq!(&buffer[4..], source.as_slice());
+
+        // A source that runs dry leaves only what it delivered.
+        let mut buffer = Vec::new();
+        let mut remaining = 3;
+        let error = unsafe {
+            fill_uninit(&mut buffer, 10, |spare| {
+                let n = spare.len().min(remaining);
+                spare[..n].fill(MaybeUninit::new(0xaa));
+                remaining -= n;
+                Ok(n)
+            })
+        }
+        .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Disconnected);
+        assert_eq!(buffer, [0xaa; 3]);
+
+        // Over-reporting is caught instead of exposing uninitialized memory.
+        let mut buffer = Vec::new();
+        assert!(unsafe { fill_uninit(&mut buffer, 4, |spare| Ok(spare.len() + 1)) }.is_err());
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_large_message_round_trip() {
+        let (queue, reader) = loopback(None).unwrap();
+        let message = (0..8 << 20).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        std::thread::scope(|scope| {
+            scope.spawn(|| queue.send(&message).unwrap());
+            assert_eq!(reader.receive().unwrap(), message);
+        });
+    }
+
+    #[test]
+    fn test_send_writes_encoded_frames() {
+        let (mut queue, reader) = loopback(None).unwrap();
+        let message = (0..8192).map(|i| i as u8).collect::<Vec<_>>();
+        let mut expected = frame::encode(&message).unwrap();
+        queue.checksum = true;
+        expected.extend(frame::encode_checked(&message).unwrap());
+        expected.extend(frame::encode_checked(b"").unwrap());
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                queue.checksum = false;
+                queue.send(&message).unwrap();
+                queue.checksum = true;
+                queue.send(&message).unwrap();
+                queue.send(b"").unwrap();
+            });
+            let mut wire = vec![0; expected.len()];
+            read_all(reader.read_fd.as_raw_fd(), &mut wire).unwrap();
+            assert!(wire == expected);
+        });
+    }
+
+    #[test]
+    fn test_receive_into_reuses_buffer() {
+        let (queue, reader) = loopback(None).unwrap();
+        let mut buffer = Vec::with_capacity(1024);
+        buffer.extend_from_slice(b"left over");
+        let capacity = buffer.capacity();
+
+        queue.send(b"Hello, reader!").unwrap();
+        assert_eq!(reader.receive_into(&mut buffer).unwrap(), 14);
+        assert_eq!(buffer, b"Hello, reader!");
+        assert_eq!(buffer.capacity(), capacity);
+
+        let large = (0..4096).map(|i| i as u8).collect::<Vec<_>>();
+        std::thread::scope(|scope| {
+            scope.spawn(|| queue.send(&large).unwrap());
+            assert_eq!(reader.receive_into(&mut buffer).unwrap(), large.len());
+        });
+        assert_eq!(buffer, large);
+    }
+
+    #[test]
+    fn test_raw_fd_round_trip() {
+        let (queue, reader) = loopback(None).unwrap();
+        let queue = unsafe { PipeQueue::from_raw_fd(queue.into_raw_fd()) };
+        let reader = unsafe { PipeReader::from_raw_fd(reader.into_raw_fd()) };
+        queue.send(b"Hello, reader!").unwrap();
+        assert_eq!(reader.receive().unwrap(), b"Hello, reader!");
+    }
+
+    #[test]
+    fn test_from_owned_fd_checks_file_type() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("my_queue");
+        let (queue, reader) = loopback(Some(&path)).unwrap();
+        let fd = unsafe { OwnedFd::from_raw_fd(reader.into_raw_fd()) };
+        let reader = PipeReader::from_owned_fd(fd).unwrap();
+        queue.send(b"Hello, reader!").unwrap();
+        assert_eq!(reader.receive().unwrap(), b"Hello, reader!");
+
+        let file_path = temp_dir.path().join("regular_file");
+        std::fs::write(&file_path, b"").unwrap();
+        let file = std::fs::File::open(&file_path).unwrap();
+        let error = PipeReader::from_owned_fd(file.into()).err().unwrap();
+        assert!(error.to_string().contains("not a pipe or FIFO"), "{error}");
+    }
+
+    #[test]
+    fn test_failed_unlock_does_not_panic() {
+        assert!(flock(-1, libc::LOCK_UN).is_err());
+        let mutex = Mutex::new(Vec::new());
+        drop(AdvisoryLock {
+            fd: -1,
+            discarded: mutex.lock().unwrap(),
+        });
+    }
+}
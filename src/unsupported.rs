@@ -0,0 +1,143 @@
+//! Stand-ins for targets without FIFOs. The public types exist so that dependent crates still
+//! compile, but every constructor fails and the types can never be instantiated.
+
+use std::{convert::Infallible, marker::PhantomData, path::Path, time::Duration};
+
+use crate::{
+    audit::AuditReport,
+    error::*,
+    frame::FrameMeta,
+    options::{Options, QueueOptions, ReaderOptions},
+};
+
+fn unsupported() -> Error {
+    Error::with_kind(
+        "quipe is not supported on this target (FIFOs require a Unix platform)",
+        ErrorKind::Unsupported,
+    )
+}
+
+pub fn audit(_path: &Path) -> Result<AuditReport> {
+    Err(unsupported())
+}
+
+pub fn loopback(_path: Option<&Path>) -> Result<(PipeQueue, PipeReader)> {
+    Err(unsupported())
+}
+
+impl QueueOptions {
+    pub fn open(&self, _path: &Path) -> Result<PipeQueue> {
+        Err(unsupported())
+    }
+}
+
+impl ReaderOptions {
+    pub fn open(&self, _path: &Path) -> Result<PipeReader> {
+        Err(unsupported())
+    }
+}
+
+pub struct PipeQueue {
+    never: Infallible,
+}
+
+pub struct PipeReader {
+    never: Infallible,
+}
+
+pub struct Drain<'a> {
+    never: Infallible,
+    _reader: PhantomData<&'a PipeReader>,
+}
+
+impl Iterator for Drain<'_> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.never {}
+    }
+}
+
+impl PipeQueue {
+    pub fn create(_path: &Path) -> Result<Self> {
+        Err(unsupported())
+    }
+
+    pub fn create_with_options(_path: &Path, _options: Options) -> Result<Self> {
+        Err(unsupported())
+    }
+
+    pub fn create_wait(_path: &Path, _timeout: Duration) -> Result<Self> {
+        Err(unsupported())
+    }
+
+    pub fn max_message_len(&self) -> usize {
+        match self.never {}
+    }
+
+    pub fn set_max_message_len(&mut self, _max_message_len: usize) {
+        match self.never {}
+    }
+
+    pub fn send(&self, _data: &[u8]) -> Result<()> {
+        match self.never {}
+    }
+
+    pub fn send_batch<I>(&self, _messages: I) -> std::result::Result<usize, BatchError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        match self.never {}
+    }
+}
+
+impl PipeReader {
+    pub fn new(_path: &Path) -> Result<Self> {
+        Err(unsupported())
+    }
+
+    pub fn new_with_options(_path: &Path, _options: Options) -> Result<Self> {
+        Err(unsupported())
+    }
+
+    pub fn max_message_len(&self) -> usize {
+        match self.never {}
+    }
+
+    pub fn set_max_message_len(&mut self, _max_message_len: usize) {
+        match self.never {}
+    }
+
+    pub fn set_timestamps(&mut self, _timestamps: bool) {
+        match self.never {}
+    }
+
+    pub fn receive(&self) -> Result<Vec<u8>> {
+        match self.never {}
+    }
+
+    pub fn receive_into(&self, _buffer: &mut Vec<u8>) -> Result<usize> {
+        match self.never {}
+    }
+
+    pub fn drain(&self) -> Drain<'_> {
+        match self.never {}
+    }
+
+    pub fn try_receive(&self) -> Result<Option<Vec<u8>>> {
+        match self.never {}
+    }
+
+    pub fn receive_timeout(&self, _timeout: Duration) -> Result<Option<Vec<u8>>> {
+        match self.never {}
+    }
+
+    pub fn receive_with_meta(&self) -> Result<(FrameMeta, Vec<u8>)> {
+        match self.never {}
+    }
+
+    pub fn resync(&self) -> Result<Vec<u8>> {
+        match self.never {}
+    }
+}
@@ -0,0 +1,54 @@
+//! Keeps the checked-in wire-format vectors in `tests/vectors` in sync with the implementation.
+
+use std::path::PathBuf;
+
+use quipe::conformance::{self, FramingMode};
+
+fn vectors_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("vectors")
+}
+
+#[test]
+fn test_checked_in_vectors_match_implementation() {
+    let dir = vectors_dir();
+    if std::env::var_os("QUIPE_REGENERATE_VECTORS").is_some() {
+        conformance::write_vectors(&dir).unwrap();
+    }
+
+    let recorded = conformance::read_vectors(&dir).unwrap();
+    assert_eq!(
+        recorded,
+        conformance::vectors().unwrap(),
+        "tests/vectors is out of date; rerun with QUIPE_REGENERATE_VECTORS=1 if the change is intended"
+    );
+    for vector in &recorded {
+        conformance::check_frame(vector.mode, &vector.payload, &vector.frame)
+            .unwrap_or_else(|error| panic!("{}: {error}", vector.name));
+    }
+}
+
+#[test]
+fn test_check_frame_rejects_mismatches() {
+    let mode = FramingMode::Plain;
+    conformance::check_frame(mode, b"abc", &[0, 0, 0, 3, b'a', b'b', b'c']).unwrap();
+    // Little-endian length.
+    assert!(conformance::check_frame(mode, b"abc", &[3, 0, 0, 0, b'a', b'b', b'c']).is_err());
+    // Truncated payload.
+    assert!(conformance::check_frame(mode, b"abc", &[0, 0, 0, 3, b'a', b'b']).is_err());
+    // Different payload.
+    assert!(conformance::check_frame(mode, b"abd", &[0, 0, 0, 3, b'a', b'b', b'c']).is_err());
+
+    let mode = FramingMode::Checked;
+    let frame = [
+        0xff, b'Q', b'P', b'F', 0x01, 0, 0, 0, 3, 0x35, 0x24, 0x41, 0xc2, b'a', b'b', b'c',
+    ];
+    conformance::check_frame(mode, b"abc", &frame).unwrap();
+    // Checksum that doesn't match the payload.
+    let mut corrupted = frame;
+    corrupted[12] ^= 1;
+    assert!(conformance::check_frame(mode, b"abc", &corrupted).is_err());
+    // A plain frame isn't a checked one.
+    assert!(conformance::check_frame(mode, b"abc", &[0, 0, 0, 3, b'a', b'b', b'c']).is_err());
+}
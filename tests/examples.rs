@@ -0,0 +1,134 @@
+//! Runs the programs in `examples/` end-to-end. `cargo test` only builds examples for some of its
+//! targets, so the binaries are built here, into a target directory of their own so that this
+//! never waits on the lock of the build that is running the tests.
+
+#![cfg(unix)]
+
+use std::{
+    collections::BTreeSet,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    sync::OnceLock,
+};
+
+use tempfile::tempdir;
+
+const EXAMPLES: [&str; 4] = [
+    "producer_consumer",
+    "resilient",
+    "typed_channel",
+    "worker_pool",
+];
+
+fn examples_dir() -> &'static Path {
+    static DIR: OnceLock<PathBuf> = OnceLock::new();
+    DIR.get_or_init(|| {
+        let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("examples");
+        let mut build = Command::new(env!("CARGO"));
+        build
+            .arg("build")
+            .arg("--manifest-path")
+            .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"))
+            .arg("--target-dir")
+            .arg(&target_dir)
+            // For `typed_channel`.
+            .arg("--features")
+            .arg("serde");
+        for example in EXAMPLES {
+            build.arg("--example").arg(example);
+        }
+        let output = build.output().unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        target_dir.join("debug").join("examples")
+    })
+}
+
+fn run_example(name: &str, args: &[&OsStr]) -> Output {
+    let output = Command::new(examples_dir().join(name))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+#[test]
+fn test_producer_consumer_example() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("my_queue");
+
+    let output = run_example("producer_consumer", &[path.as_os_str(), "5".as_ref()]);
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let expected = (0..5).map(|i| format!("message {i}")).collect::<Vec<_>>();
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn test_worker_pool_example() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("my_queue");
+
+    let output = run_example(
+        "worker_pool",
+        &[path.as_os_str(), "100".as_ref(), "3".as_ref()],
+    );
+
+    // Every job is handled exactly once, by one of the three workers.
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut jobs = BTreeSet::new();
+    for line in stdout.lines() {
+        let (worker, job) = line.split_once(": ").unwrap();
+        assert!(
+            ["worker 0", "worker 1", "worker 2"].contains(&worker),
+            "{line}"
+        );
+        let job = job.strip_prefix("job ").unwrap().parse::<usize>().unwrap();
+        assert!(jobs.insert(job), "job {job} was handled twice");
+    }
+    assert_eq!(jobs, (0..100).collect());
+}
+
+#[test]
+fn test_typed_channel_example() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("my_queue");
+
+    let output = run_example("typed_channel", &[path.as_os_str(), "4".as_ref()]);
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        [
+            "order 0: 1 x item-0",
+            "order 1: 2 x item-1",
+            "order 2: 3 x item-2",
+            "order 3: 4 x item-0",
+        ]
+    );
+}
+
+#[test]
+fn test_resilient_example() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("my_queue");
+
+    let output = run_example(
+        "resilient",
+        &[path.as_os_str(), "10".as_ref(), "3".as_ref()],
+    );
+
+    // Nothing is lost or repeated across the reader restarts.
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let expected = (0..10).map(|i| format!("message {i}")).collect::<Vec<_>>();
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), expected);
+}
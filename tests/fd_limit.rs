@@ -0,0 +1,42 @@
+//! Runs out of file descriptors on purpose, so that opening a FIFO fails after it was created. It
+//! lives in its own test binary so no other test runs short of descriptors.
+
+#![cfg(unix)]
+
+use std::fs::File;
+
+use quipe::loopback;
+use tempfile::tempdir;
+
+#[test]
+fn test_loopback_removes_fifo_when_open_fails() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("my_queue");
+
+    // Lower the limit so using up every descriptor is quick, then restore it.
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    assert_eq!(
+        unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) },
+        0
+    );
+    let lowered = libc::rlimit {
+        rlim_cur: limit.rlim_cur.min(256),
+        ..limit
+    };
+    assert_eq!(unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &lowered) }, 0);
+    let mut files = Vec::new();
+    while let Ok(file) = File::open("/dev/null") {
+        files.push(file);
+    }
+
+    let error = loopback(Some(&path)).err();
+
+    drop(files);
+    assert_eq!(unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) }, 0);
+    let error = error.unwrap();
+    assert_eq!(error.raw_os_error(), Some(libc::EMFILE));
+    assert!(!path.exists());
+}
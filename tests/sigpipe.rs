@@ -0,0 +1,27 @@
+//! Sends to a queue whose readers are gone with SIGPIPE at its default disposition, which kills
+//! the process unless the send keeps the signal from being delivered. The Rust runtime ignores
+//! SIGPIPE at startup, so this restores the default first; it lives in its own test binary so no
+//! other test runs with it.
+
+#![cfg(unix)]
+
+use quipe::{ErrorKind, PipeQueue, PipeReader};
+use tempfile::tempdir;
+
+#[test]
+fn test_send_after_reader_drops_survives_sigpipe() {
+    unsafe { libc::signal(libc::SIGPIPE, libc::SIG_DFL) };
+
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("my_queue");
+    let queue = PipeQueue::create(&path).unwrap();
+    let reader = PipeReader::new(&path).unwrap();
+    queue.send(b"Hello, reader!").unwrap();
+    assert_eq!(reader.receive().unwrap(), b"Hello, reader!");
+
+    drop(reader);
+    for _ in 0..2 {
+        let error = queue.send(b"Hello, nobody!").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NoReaders);
+    }
+}
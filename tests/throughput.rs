@@ -0,0 +1,61 @@
+//! Compares the allocating and buffer-reusing receive paths. Timing is noisy, so this only checks
+//! that every message arrives intact and prints the rates:
+//!
+//!     cargo test --release --test throughput -- --ignored --nocapture
+
+#![cfg(unix)]
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use quipe::{loopback, PipeReader};
+
+const BYTES: usize = 64 << 20;
+const MAX_MESSAGES: usize = 200_000;
+
+/// Sends `count` messages of `len` bytes while `receive` consumes and checks each one, and returns
+/// how long the whole exchange took.
+fn run(len: usize, count: usize, mut receive: impl FnMut(&PipeReader, &[u8])) -> Duration {
+    let (queue, reader) = loopback(None).unwrap();
+    // Built up front, so the only allocations being timed are the receiver's.
+    let messages = (0..=u8::MAX).map(|i| vec![i; len]).collect::<Vec<_>>();
+    let message = |i: usize| messages[i % messages.len()].as_slice();
+    let start = Instant::now();
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            for i in 0..count {
+                queue.send(message(i)).unwrap();
+            }
+        });
+        for i in 0..count {
+            receive(&reader, message(i));
+        }
+    });
+    start.elapsed()
+}
+
+#[test]
+#[ignore = "benchmark; run with --release --ignored --nocapture"]
+fn test_receive_throughput() {
+    for len in [64, 4096, 65536] {
+        let count = (BYTES / len).min(MAX_MESSAGES);
+        let report = |name: &str, elapsed: Duration| {
+            let rate = count as f64 / elapsed.as_secs_f64();
+            println!("{len:>6} B  {name:<14} {elapsed:>10.2?} {rate:>10.0} msg/s");
+        };
+
+        let elapsed = run(len, count, |reader, expected| {
+            assert_eq!(reader.receive().unwrap(), expected);
+        });
+        report("receive", elapsed);
+
+        let mut buffer = Vec::new();
+        let elapsed = run(len, count, |reader, expected| {
+            reader.receive_into(&mut buffer).unwrap();
+            assert_eq!(buffer, expected);
+        });
+        report("receive_into", elapsed);
+    }
+}